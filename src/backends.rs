@@ -0,0 +1,149 @@
+use syn::parse_quote;
+
+use crate::generator::Generator;
+
+/// Generates constants holding the shader translated by each of the enabled naga backends, so that targets using
+/// them don't need to parse WGSL at runtime.
+///
+/// Overrides are replaced by their default values, as the backends require them to be resolved. Nothing is generated
+/// for shaders with an override without a default, as their translation depends on values that are only known once a
+/// pipeline is created. Every backend is tried even if another fails, so that all of their failures are reported
+/// together.
+pub(crate) fn backend_items(
+    generator: &Generator,
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+) -> Result<Vec<syn::Item>, String> {
+    if module
+        .overrides
+        .iter()
+        .any(|(_, override_)| override_.init.is_none())
+    {
+        return Ok(Vec::new());
+    }
+    let (module, info) =
+        naga::back::pipeline_constants::process_overrides(module, info, &Default::default())
+            .map_err(|e| format!("failed to resolve overrides for translation: {}", e))?;
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    let mut add = |result: Result<Option<syn::Item>, String>| match result {
+        Ok(item) => items.extend(item),
+        Err(e) => errors.push(e),
+    };
+
+    #[cfg(feature = "spirv")]
+    if generator.gen_spirv {
+        add(spirv(&module, &info).map(Some));
+    }
+    #[cfg(feature = "msl")]
+    if generator.gen_msl {
+        add(msl(&module, &info, &generator.msl_options).map(Some));
+    }
+    #[cfg(feature = "hlsl")]
+    if generator.gen_hlsl {
+        add(hlsl(&module, &info, &generator.hlsl_options).map(Some));
+    }
+    #[cfg(feature = "glsl")]
+    if generator.gen_glsl {
+        add(glsl(&module, &info, &generator.glsl_options));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    Ok(items)
+}
+
+#[cfg(feature = "spirv")]
+fn spirv(module: &naga::Module, info: &naga::valid::ModuleInfo) -> Result<syn::Item, String> {
+    let words = naga::back::spv::write_vec(module, info, &Default::default(), None)
+        .map_err(|e| format!("failed to translate to SPIR-V: {}", e))?;
+    let words = words.into_iter().map(proc_macro2::Literal::u32_unsuffixed);
+    Ok(parse_quote! {
+        pub const SPIRV: &[u32] = &[#(#words),*];
+    })
+}
+
+#[cfg(feature = "msl")]
+fn msl(
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+    options: &naga::back::msl::Options,
+) -> Result<syn::Item, String> {
+    let (source, _) = naga::back::msl::write_string(module, info, options, &Default::default())
+        .map_err(|e| format!("failed to translate to MSL: {}", e))?;
+    Ok(parse_quote! {
+        pub const MSL: &str = #source;
+    })
+}
+
+#[cfg(feature = "hlsl")]
+fn hlsl(
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+    options: &naga::back::hlsl::Options,
+) -> Result<syn::Item, String> {
+    let mut source = String::new();
+    naga::back::hlsl::Writer::new(&mut source, options)
+        .write(module, info, None)
+        .map_err(|e| format!("failed to translate to HLSL: {}", e))?;
+    Ok(parse_quote! {
+        pub const HLSL: &str = #source;
+    })
+}
+
+/// GLSL only supports a single entry point per shader, so gives a module with a constant for each entry point.
+#[cfg(feature = "glsl")]
+fn glsl(
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+    options: &naga::back::glsl::Options,
+) -> Result<Option<syn::Item>, String> {
+    if module.entry_points.is_empty() {
+        return Ok(None);
+    }
+
+    let mut entry_points = Vec::<syn::Item>::new();
+    let mut errors = Vec::new();
+    for entry_point in &module.entry_points {
+        let pipeline_options = naga::back::glsl::PipelineOptions {
+            shader_stage: entry_point.stage,
+            entry_point: entry_point.name.clone(),
+            multiview: None,
+        };
+        let mut source = String::new();
+        let result = naga::back::glsl::Writer::new(
+            &mut source,
+            module,
+            info,
+            options,
+            &pipeline_options,
+            Default::default(),
+        )
+        .and_then(|mut writer| writer.write());
+        if let Err(e) = result {
+            errors.push(format!(
+                "failed to translate entry point `{}` to GLSL: {}",
+                entry_point.name, e
+            ));
+            continue;
+        }
+
+        let name = quote::format_ident!(
+            "{}",
+            crate::layout::sanitize_ident(&entry_point.name.to_uppercase())
+        );
+        entry_points.push(parse_quote! {
+            pub const #name: &str = #source;
+        });
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    Ok(Some(parse_quote! {
+        pub mod glsl {
+            #(#entry_points)*
+        }
+    }))
+}
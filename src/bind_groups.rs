@@ -0,0 +1,691 @@
+use std::collections::{BTreeMap, HashSet};
+
+use naga::valid::ModuleInfo;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse_quote;
+
+use crate::{error::decompose_mangled_name, layout::sanitize_ident};
+
+/// The name of the generated module containing bind group layouts and builders.
+const BIND_GROUPS_MODULE_NAME: &str = "bind_groups";
+
+/// The kind of wgpu resource that a binding takes.
+#[derive(Clone, Copy)]
+enum Resource {
+    Buffer,
+    TextureView,
+    Sampler,
+}
+
+/// A single resource binding within a bind group.
+struct Binding {
+    binding: u32,
+    name: syn::Ident,
+    resource: Resource,
+    /// The number of resources, if the binding is a binding array.
+    count: Option<u32>,
+    binding_type: TokenStream,
+    visibility: TokenStream,
+}
+
+impl Binding {
+    fn layout_entry(&self) -> TokenStream {
+        let Self {
+            binding,
+            binding_type,
+            visibility,
+            ..
+        } = self;
+        let count = match self.count {
+            Some(count) => quote! { ::core::num::NonZeroU32::new(#count) },
+            None => quote! { None },
+        };
+        quote! {
+            wgpu::BindGroupLayoutEntry {
+                binding: #binding,
+                visibility: #visibility,
+                ty: #binding_type,
+                count: #count,
+            }
+        }
+    }
+
+    fn field_type(&self) -> TokenStream {
+        match (self.resource, self.count.is_some()) {
+            (Resource::Buffer, false) => quote! { wgpu::BufferBinding<'a> },
+            (Resource::Buffer, true) => quote! { &'a [wgpu::BufferBinding<'a>] },
+            (Resource::TextureView, false) => quote! { &'a wgpu::TextureView },
+            (Resource::TextureView, true) => quote! { &'a [&'a wgpu::TextureView] },
+            (Resource::Sampler, false) => quote! { &'a wgpu::Sampler },
+            (Resource::Sampler, true) => quote! { &'a [&'a wgpu::Sampler] },
+        }
+    }
+
+    fn bind_group_entry(&self) -> TokenStream {
+        let Self { binding, name, .. } = self;
+        let resource = match (self.resource, self.count.is_some()) {
+            (Resource::Buffer, false) => quote! { Buffer(self.#name.clone()) },
+            (Resource::Buffer, true) => quote! { BufferArray(self.#name) },
+            (Resource::TextureView, false) => quote! { TextureView(self.#name) },
+            (Resource::TextureView, true) => quote! { TextureViewArray(self.#name) },
+            (Resource::Sampler, false) => quote! { Sampler(self.#name) },
+            (Resource::Sampler, true) => quote! { SamplerArray(self.#name) },
+        };
+        quote! {
+            wgpu::BindGroupEntry {
+                binding: #binding,
+                resource: wgpu::BindingResource::#resource,
+            }
+        }
+    }
+}
+
+/// Generates a module containing, for each bind group used by a shader, a constant array of wgpu layout entries
+/// and a struct holding the resources to bind with a method to create the bind group.
+///
+/// Gives `None` if the shader has no resource bindings.
+pub(crate) fn bind_groups_module(
+    module: &naga::Module,
+    info: &ModuleInfo,
+    label: &str,
+) -> Result<Option<syn::Item>, String> {
+    let mut layouter = naga::proc::Layouter::default();
+    layouter
+        .update(module.to_ctx())
+        .map_err(|e| format!("failed to compute type layouts: {}", e))?;
+
+    // Textures only ever loaded from can take formats that can't be filtered
+    let sampled = (0..module.entry_points.len())
+        .flat_map(|i| &info.get_entry_point(i).sampling_set)
+        .map(|key| key.image)
+        .collect::<HashSet<_>>();
+
+    let mut groups = BTreeMap::<u32, Vec<Binding>>::new();
+    for (handle, global) in module.global_variables.iter() {
+        let Some(resource_binding) = &global.binding else {
+            continue;
+        };
+        let name = match &global.name {
+            Some(name) => match decompose_mangled_name(name) {
+                Some((_, name)) => name.to_owned(),
+                None => name.clone(),
+            },
+            None => format!("binding{}", resource_binding.binding),
+        };
+
+        // Binding arrays take the layout of their elements
+        let (ty, count) = match &module.types[global.ty].inner {
+            naga::TypeInner::BindingArray { base, size } => match size {
+                naga::ArraySize::Constant(size) => (*base, Some(size.get())),
+                _ => {
+                    return Err(format!(
+                        "binding array `{}` must have a constant size to generate its bind group layout",
+                        name
+                    ))
+                }
+            },
+            _ => (global.ty, None),
+        };
+        let (resource, binding_type) = binding_type(
+            module,
+            &layouter,
+            global.space,
+            ty,
+            sampled.contains(&handle),
+        )
+        .map_err(|kind| {
+            format!(
+                "cannot generate a bind group layout for `{}`: {} bindings are not supported",
+                name, kind
+            )
+        })?;
+
+        let stages = [
+            (naga::ShaderStage::Vertex, quote! { VERTEX }),
+            (naga::ShaderStage::Fragment, quote! { FRAGMENT }),
+            (naga::ShaderStage::Compute, quote! { COMPUTE }),
+        ]
+        .into_iter()
+        .filter(|(stage, _)| {
+            module
+                .entry_points
+                .iter()
+                .enumerate()
+                .any(|(i, entry_point)| {
+                    entry_point.stage == *stage && !info.get_entry_point(i)[handle].is_empty()
+                })
+        })
+        .map(|(_, stage)| quote! { wgpu::ShaderStages::#stage });
+        let visibility = stages
+            .reduce(|visibility, stage| quote! { #visibility.union(#stage) })
+            .unwrap_or_else(|| quote! { wgpu::ShaderStages::NONE });
+
+        groups
+            .entry(resource_binding.group)
+            .or_default()
+            .push(Binding {
+                binding: resource_binding.binding,
+                name: format_ident!("{}", sanitize_ident(&name)),
+                resource,
+                count,
+                binding_type,
+                visibility,
+            });
+    }
+
+    if groups.is_empty() {
+        return Ok(None);
+    }
+
+    let items = groups.into_iter().map(|(group, mut bindings)| {
+        bindings.sort_by_key(|binding| binding.binding);
+
+        // Bindings from different files may share a name, so later ones are distinguished by their binding number
+        let mut field_names = HashSet::new();
+        for binding in &mut bindings {
+            let mut name = binding.name.clone();
+            let mut repeats = 0u32;
+            while !field_names.insert(name.to_string()) {
+                repeats += 1;
+                name = match repeats {
+                    1 => format_ident!("{}_{}", binding.name, binding.binding),
+                    _ => format_ident!("{}_{}_{}", binding.name, binding.binding, repeats),
+                };
+            }
+            binding.name = name;
+        }
+
+        let entries_name = format_ident!("GROUP{}_LAYOUT_ENTRIES", group);
+        let descriptor_name = format_ident!("GROUP{}_LAYOUT_DESCRIPTOR", group);
+        let struct_name = format_ident!("BindGroup{}", group);
+        let layout_label = format!("{} bind group layout {}", label, group);
+        let bind_group_label = format!("{} bind group {}", label, group);
+        let entry_count = bindings.len();
+        let layout_entries = bindings.iter().map(Binding::layout_entry);
+        let field_names = bindings.iter().map(|binding| &binding.name);
+        let field_types = bindings.iter().map(Binding::field_type);
+        let bind_group_entries = bindings.iter().map(Binding::bind_group_entry);
+
+        quote! {
+            pub const #entries_name: [wgpu::BindGroupLayoutEntry; #entry_count] = [
+                #(#layout_entries),*
+            ];
+
+            pub const #descriptor_name: wgpu::BindGroupLayoutDescriptor<'static> =
+                wgpu::BindGroupLayoutDescriptor {
+                    label: Some(#layout_label),
+                    entries: &#entries_name,
+                };
+
+            #[derive(Clone, Debug)]
+            pub struct #struct_name<'a> {
+                #(pub #field_names: #field_types,)*
+            }
+
+            impl #struct_name<'_> {
+                pub const GROUP: u32 = #group;
+
+                pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+                    device.create_bind_group_layout(&#descriptor_name)
+                }
+
+                pub fn create_bind_group(
+                    &self,
+                    device: &wgpu::Device,
+                    layout: &wgpu::BindGroupLayout,
+                ) -> wgpu::BindGroup {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some(#bind_group_label),
+                        layout,
+                        entries: &[#(#bind_group_entries),*],
+                    })
+                }
+            }
+        }
+    });
+
+    let module_name = format_ident!("{}", BIND_GROUPS_MODULE_NAME);
+    Ok(Some(parse_quote! {
+        pub mod #module_name {
+            #(#items)*
+        }
+    }))
+}
+
+/// Gives the wgpu binding type of a global variable's type within an address space, or the kind of binding if it
+/// is not supported. Float textures are only filterable if `sampled` is set, as they are then used with samplers.
+fn binding_type(
+    module: &naga::Module,
+    layouter: &naga::proc::Layouter,
+    space: naga::AddressSpace,
+    ty: naga::Handle<naga::Type>,
+    sampled: bool,
+) -> Result<(Resource, TokenStream), &'static str> {
+    let buffer = |buffer_type: TokenStream| {
+        let size = u64::from(layouter[ty].size);
+        (
+            Resource::Buffer,
+            quote! {
+                wgpu::BindingType::Buffer {
+                    ty: #buffer_type,
+                    has_dynamic_offset: false,
+                    min_binding_size: ::core::num::NonZeroU64::new(#size),
+                }
+            },
+        )
+    };
+
+    match space {
+        naga::AddressSpace::Uniform => {
+            return Ok(buffer(quote! { wgpu::BufferBindingType::Uniform }))
+        }
+        naga::AddressSpace::Storage { access } => {
+            let read_only = !access.contains(naga::StorageAccess::STORE);
+            return Ok(buffer(
+                quote! { wgpu::BufferBindingType::Storage { read_only: #read_only } },
+            ));
+        }
+        naga::AddressSpace::Handle => {}
+        _ => return Err("non-resource"),
+    }
+
+    match &module.types[ty].inner {
+        naga::TypeInner::Sampler { comparison } => {
+            let sampler_type = if *comparison {
+                quote! { Comparison }
+            } else {
+                quote! { Filtering }
+            };
+            Ok((
+                Resource::Sampler,
+                quote! { wgpu::BindingType::Sampler(wgpu::SamplerBindingType::#sampler_type) },
+            ))
+        }
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => quote! { D1 },
+                (naga::ImageDimension::D2, false) => quote! { D2 },
+                (naga::ImageDimension::D2, true) => quote! { D2Array },
+                (naga::ImageDimension::D3, _) => quote! { D3 },
+                (naga::ImageDimension::Cube, false) => quote! { Cube },
+                (naga::ImageDimension::Cube, true) => quote! { CubeArray },
+            };
+            let view_dimension = quote! { wgpu::TextureViewDimension::#view_dimension };
+
+            let binding_type = match class {
+                naga::ImageClass::Sampled { kind, multi } => {
+                    let sample_type = match kind {
+                        // Multisampled textures cannot be filtered
+                        naga::ScalarKind::Float => {
+                            let filterable = sampled && !multi;
+                            quote! { Float { filterable: #filterable } }
+                        }
+                        naga::ScalarKind::Sint => quote! { Sint },
+                        naga::ScalarKind::Uint => quote! { Uint },
+                        _ => return Err("texture sample"),
+                    };
+                    quote! {
+                        wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::#sample_type,
+                            view_dimension: #view_dimension,
+                            multisampled: #multi,
+                        }
+                    }
+                }
+                naga::ImageClass::Depth { multi } => quote! {
+                    wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: #view_dimension,
+                        multisampled: #multi,
+                    }
+                },
+                naga::ImageClass::Storage { format, access } => {
+                    let format = texture_format(*format);
+                    let access = match (
+                        access.contains(naga::StorageAccess::LOAD),
+                        access.contains(naga::StorageAccess::STORE),
+                    ) {
+                        (true, true) => quote! { ReadWrite },
+                        (false, true) => quote! { WriteOnly },
+                        _ => quote! { ReadOnly },
+                    };
+                    quote! {
+                        wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::#access,
+                            format: #format,
+                            view_dimension: #view_dimension,
+                        }
+                    }
+                }
+            };
+            Ok((Resource::TextureView, binding_type))
+        }
+        naga::TypeInner::AccelerationStructure => Err("acceleration structure"),
+        _ => Err("handle"),
+    }
+}
+
+/// Gives the wgpu texture format equivalent to a naga storage format.
+fn texture_format(format: naga::StorageFormat) -> TokenStream {
+    use naga::StorageFormat as Sf;
+
+    let format = match format {
+        Sf::R8Unorm => quote! { R8Unorm },
+        Sf::R8Snorm => quote! { R8Snorm },
+        Sf::R8Uint => quote! { R8Uint },
+        Sf::R8Sint => quote! { R8Sint },
+        Sf::R16Uint => quote! { R16Uint },
+        Sf::R16Sint => quote! { R16Sint },
+        Sf::R16Float => quote! { R16Float },
+        Sf::Rg8Unorm => quote! { Rg8Unorm },
+        Sf::Rg8Snorm => quote! { Rg8Snorm },
+        Sf::Rg8Uint => quote! { Rg8Uint },
+        Sf::Rg8Sint => quote! { Rg8Sint },
+        Sf::R32Uint => quote! { R32Uint },
+        Sf::R32Sint => quote! { R32Sint },
+        Sf::R32Float => quote! { R32Float },
+        Sf::Rg16Uint => quote! { Rg16Uint },
+        Sf::Rg16Sint => quote! { Rg16Sint },
+        Sf::Rg16Float => quote! { Rg16Float },
+        Sf::Rgba8Unorm => quote! { Rgba8Unorm },
+        Sf::Rgba8Snorm => quote! { Rgba8Snorm },
+        Sf::Rgba8Uint => quote! { Rgba8Uint },
+        Sf::Rgba8Sint => quote! { Rgba8Sint },
+        Sf::Bgra8Unorm => quote! { Bgra8Unorm },
+        Sf::Rgb10a2Uint => quote! { Rgb10a2Uint },
+        Sf::Rgb10a2Unorm => quote! { Rgb10a2Unorm },
+        Sf::Rg11b10Ufloat => quote! { Rg11b10Ufloat },
+        Sf::R64Uint => quote! { R64Uint },
+        Sf::Rg32Uint => quote! { Rg32Uint },
+        Sf::Rg32Sint => quote! { Rg32Sint },
+        Sf::Rg32Float => quote! { Rg32Float },
+        Sf::Rgba16Uint => quote! { Rgba16Uint },
+        Sf::Rgba16Sint => quote! { Rgba16Sint },
+        Sf::Rgba16Float => quote! { Rgba16Float },
+        Sf::Rgba32Uint => quote! { Rgba32Uint },
+        Sf::Rgba32Sint => quote! { Rgba32Sint },
+        Sf::Rgba32Float => quote! { Rgba32Float },
+        Sf::R16Unorm => quote! { R16Unorm },
+        Sf::R16Snorm => quote! { R16Snorm },
+        Sf::Rg16Unorm => quote! { Rg16Unorm },
+        Sf::Rg16Snorm => quote! { Rg16Snorm },
+        Sf::Rgba16Unorm => quote! { Rgba16Unorm },
+        Sf::Rgba16Snorm => quote! { Rgba16Snorm },
+    };
+    quote! { wgpu::TextureFormat::#format }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+
+    use super::*;
+
+    /// Gives the layout entries of each bind group generated for a shader.
+    fn layout_entries(source: &str) -> Vec<String> {
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .unwrap();
+        let Some(syn::Item::Mod(item)) = bind_groups_module(&module, &info, "test").unwrap() else {
+            panic!("expected a bind groups module");
+        };
+        item.content
+            .unwrap()
+            .1
+            .into_iter()
+            .filter_map(|item| match item {
+                syn::Item::Const(item) if item.ident.to_string().ends_with("_LAYOUT_ENTRIES") => {
+                    Some(item.expr.to_token_stream().to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn entries(entries: &[TokenStream]) -> String {
+        quote! { [#(#entries),*] }.to_string()
+    }
+
+    #[test]
+    fn buffers() {
+        let source = "
+            struct Light { color: vec4<f32>, intensity: f32 }
+            @group(0) @binding(0) var<uniform> light: Light;
+            @group(0) @binding(1) var<storage, read> lights: array<Light>;
+            @group(1) @binding(0) var<storage, read_write> counts: array<u32>;
+
+            @compute @workgroup_size(1)
+            fn main() { counts[0] = u32(light.intensity + lights[0].intensity); }
+        ";
+
+        assert_eq!(
+            layout_entries(source),
+            [
+                entries(&[
+                    quote! {
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0u32,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: ::core::num::NonZeroU64::new(32u64),
+                            },
+                            count: None,
+                        }
+                    },
+                    quote! {
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1u32,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: ::core::num::NonZeroU64::new(32u64),
+                            },
+                            count: None,
+                        }
+                    },
+                ]),
+                entries(&[quote! {
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0u32,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: ::core::num::NonZeroU64::new(4u64),
+                        },
+                        count: None,
+                    }
+                }]),
+            ]
+        );
+    }
+
+    #[test]
+    fn textures_are_filterable_only_when_sampled() {
+        let source = "
+            @group(0) @binding(0) var sampled: texture_2d<f32>;
+            @group(0) @binding(1) var loaded: texture_2d<f32>;
+            @group(0) @binding(2) var multisampled: texture_multisampled_2d<f32>;
+            @group(0) @binding(3) var linear: sampler;
+
+            @fragment
+            fn main() -> @location(0) vec4<f32> {
+                return textureSample(sampled, linear, vec2(0.0))
+                    + textureLoad(loaded, vec2(0), 0)
+                    + textureLoad(multisampled, vec2(0), 0);
+            }
+        ";
+        let texture = |binding: u32, filterable: bool, multisampled: bool| {
+            quote! {
+                wgpu::BindGroupLayoutEntry {
+                    binding: #binding,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: #filterable },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: #multisampled,
+                    },
+                    count: None,
+                }
+            }
+        };
+
+        assert_eq!(
+            layout_entries(source),
+            [entries(&[
+                texture(0, true, false),
+                texture(1, false, false),
+                texture(2, false, true),
+                quote! {
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3u32,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    }
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn comparison_samplers() {
+        let source = "
+            @group(0) @binding(0) var shadow_map: texture_depth_2d;
+            @group(0) @binding(1) var shadow_sampler: sampler_comparison;
+
+            @fragment
+            fn main() -> @location(0) vec4<f32> {
+                return vec4(textureSampleCompare(shadow_map, shadow_sampler, vec2(0.0), 0.5));
+            }
+        ";
+
+        assert_eq!(
+            layout_entries(source),
+            [entries(&[
+                quote! {
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0u32,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    }
+                },
+                quote! {
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1u32,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    }
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn field_names_are_unique() {
+        // `lights` from `lib`, as naga_oil names imported items
+        let source = "
+            @group(0) @binding(0) var<storage, read> lights: array<f32>;
+            @group(0) @binding(1) var<storage, read> lightsX_naga_oil_mod_XNRUWEX: array<f32>;
+            @group(0) @binding(2) var<storage, read> lights_1: array<f32>;
+            @group(1) @binding(0) var<uniform> in: f32;
+            @group(1) @binding(1) var<uniform> inX_naga_oil_mod_XNRUWEX: f32;
+
+            @compute @workgroup_size(1)
+            fn main() {
+                let value = lights[0] + lightsX_naga_oil_mod_XNRUWEX[0] + lights_1[0] + in
+                    + inX_naga_oil_mod_XNRUWEX;
+            }
+        ";
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .unwrap();
+        let Some(syn::Item::Mod(item)) = bind_groups_module(&module, &info, "test").unwrap() else {
+            panic!("expected a bind groups module");
+        };
+        let fields = item
+            .content
+            .unwrap()
+            .1
+            .into_iter()
+            .filter_map(|item| match item {
+                syn::Item::Struct(item) => Some(
+                    item.fields
+                        .iter()
+                        .map(|field| field.ident.to_token_stream().to_string())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            fields,
+            [
+                vec!["lights", "lights_1", "lights_1_2"],
+                vec!["r#in", "in_1"]
+            ]
+        );
+    }
+
+    #[test]
+    fn storage_textures() {
+        let source = "
+            @group(0) @binding(0) var input: texture_storage_2d<r32float, read>;
+            @group(0) @binding(1) var output: texture_storage_2d_array<rg11b10float, write>;
+            @group(0) @binding(2) var both: texture_storage_3d<rgba16unorm, read_write>;
+
+            @compute @workgroup_size(1)
+            fn main() {
+                let value = textureLoad(input, vec2(0));
+                textureStore(output, vec2(0), 0, value);
+                textureStore(both, vec3(0), textureLoad(both, vec3(0)));
+            }
+        ";
+        let texture = |binding: u32, access, format, view_dimension| {
+            quote! {
+                wgpu::BindGroupLayoutEntry {
+                    binding: #binding,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::#access,
+                        format: wgpu::TextureFormat::#format,
+                        view_dimension: wgpu::TextureViewDimension::#view_dimension,
+                    },
+                    count: None,
+                }
+            }
+        };
+
+        assert_eq!(
+            layout_entries(source),
+            [entries(&[
+                texture(0, quote!(ReadOnly), quote!(R32Float), quote!(D2)),
+                texture(1, quote!(WriteOnly), quote!(Rg11b10Ufloat), quote!(D2Array)),
+                texture(2, quote!(ReadWrite), quote!(Rgba16Unorm), quote!(D3)),
+            ])]
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use naga_oil::compose::ShaderDefValue;
+
+/// Identifies the format of cache files, so that files written by other versions are ignored.
+const CACHE_HEADER: &str = concat!("generate-wgsl-oil cache ", env!("CARGO_PKG_VERSION"));
+
+/// Hashes a value for use as, or as part of, a cache key.
+///
+/// The hash only depends on the bytes that the value's `Hash` implementation writes. Those of std types aren't
+/// guaranteed to stay the same between Rust releases either, but changing them only causes records to be missed.
+pub(crate) fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A 64-bit FNV-1a hasher. Unlike `DefaultHasher`, its algorithm is fixed, so records written by a build made with
+/// one Rust release are still found by builds made with another.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    // Lengths are hashed as `usize`, which is written with the same width on every target
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hashes the content of a file.
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<u64> {
+    std::fs::read(path).map(|content| hash(&content))
+}
+
+/// Shader defs sorted by name, as a shader was composed with.
+pub(crate) type Defs = Vec<(String, ShaderDefValue)>;
+
+/// Everything needed to generate a shader's module without composing it, as recorded when it was last composed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Record {
+    /// A hash of the generator version, the options affecting generated code and the defs of every build.
+    pub(crate) key: u64,
+    /// The absolute path of every file that the shader was composed from along with a hash of its content, starting
+    /// with the shader itself.
+    pub(crate) files: Vec<(PathBuf, u64)>,
+    /// Files that an import of any build would have resolved to instead of the file it did, had they existed.
+    pub(crate) missed_imports: Vec<PathBuf>,
+    /// The absolute path of every file imported by any build of the shader, directly or indirectly, along with the
+    /// distinct defs of the builds importing it.
+    pub(crate) imports: Vec<(PathBuf, Vec<Defs>)>,
+    /// The types used as vertex inputs by the shader's entry points, by the absolute path of the file declaring them.
+    pub(crate) vertex_inputs: Vec<(PathBuf, String)>,
+    /// The name in the shader of every struct the shader exports.
+    pub(crate) exported_structs: Vec<String>,
+    /// Exported structs given another name in Rust, by their name in the shader.
+    pub(crate) renames: Vec<(String, String)>,
+    /// A hash of everything outside of the shader that its generated items depend on, such as the locations of the
+    /// other modules.
+    pub(crate) context: u64,
+    /// The generated items, or an empty string if they haven't been generated.
+    pub(crate) tokens: String,
+}
+
+impl Record {
+    /// Gives the absolute path of the shader.
+    pub(crate) fn root_path(&self) -> &Path {
+        &self.files[0].0
+    }
+
+    fn encode(&self, shader: &str) -> String {
+        let mut encoder = Encoder::default();
+        encoder.field(CACHE_HEADER);
+        encoder.field(shader);
+        encoder.field(&self.key.to_string());
+        encoder.list(&self.files, |encoder, (path, hash)| {
+            encoder.field(&path.to_string_lossy());
+            encoder.field(&hash.to_string());
+        });
+        encoder.list(&self.missed_imports, |encoder, path| {
+            encoder.field(&path.to_string_lossy());
+        });
+        encoder.list(&self.imports, |encoder, (path, builds)| {
+            encoder.field(&path.to_string_lossy());
+            encoder.list(builds, |encoder, defs| {
+                encoder.list(defs, |encoder, (name, value)| {
+                    encoder.field(name);
+                    encoder.field(&encode_def(value));
+                });
+            });
+        });
+        encoder.list(&self.vertex_inputs, |encoder, (path, type_name)| {
+            encoder.field(&path.to_string_lossy());
+            encoder.field(type_name);
+        });
+        encoder.list(&self.exported_structs, |encoder, name| encoder.field(name));
+        encoder.list(&self.renames, |encoder, (original, name)| {
+            encoder.field(original);
+            encoder.field(name);
+        });
+        encoder.field(&self.context.to_string());
+        encoder.field(&self.tokens);
+        encoder.0
+    }
+
+    /// Reads a record previously written for the given shader, giving `None` if it is malformed or for another shader.
+    fn decode(shader: &str, encoded: &str) -> Option<Self> {
+        let mut decoder = Decoder(encoded);
+        if decoder.field()? != CACHE_HEADER || decoder.field()? != shader {
+            return None;
+        }
+
+        let key = decoder.field()?.parse().ok()?;
+        let files = decoder.list(|decoder| {
+            Some((
+                PathBuf::from(decoder.field()?),
+                decoder.field()?.parse().ok()?,
+            ))
+        })?;
+        let missed_imports = decoder.list(|decoder| Some(PathBuf::from(decoder.field()?)))?;
+        let imports = decoder.list(|decoder| {
+            let path = PathBuf::from(decoder.field()?);
+            let builds = decoder.list(|decoder| {
+                decoder.list(|decoder| {
+                    Some((decoder.field()?.to_owned(), decode_def(decoder.field()?)?))
+                })
+            })?;
+            Some((path, builds))
+        })?;
+        let vertex_inputs = decoder
+            .list(|decoder| Some((PathBuf::from(decoder.field()?), decoder.field()?.to_owned())))?;
+        let exported_structs = decoder.list(|decoder| Some(decoder.field()?.to_owned()))?;
+        let renames = decoder
+            .list(|decoder| Some((decoder.field()?.to_owned(), decoder.field()?.to_owned())))?;
+        let context = decoder.field()?.parse().ok()?;
+        let tokens = decoder.field()?.to_owned();
+
+        (decoder.0.is_empty() && !files.is_empty()).then_some(Self {
+            key,
+            files,
+            missed_imports,
+            imports,
+            vertex_inputs,
+            exported_structs,
+            renames,
+            context,
+            tokens,
+        })
+    }
+}
+
+fn encode_def(value: &ShaderDefValue) -> String {
+    match value {
+        ShaderDefValue::Bool(value) => format!("bool {}", value),
+        ShaderDefValue::Int(value) => format!("int {}", value),
+        ShaderDefValue::UInt(value) => format!("uint {}", value),
+    }
+}
+
+fn decode_def(encoded: &str) -> Option<ShaderDefValue> {
+    match encoded.split_once(' ')? {
+        ("bool", value) => value.parse().ok().map(ShaderDefValue::Bool),
+        ("int", value) => value.parse().ok().map(ShaderDefValue::Int),
+        ("uint", value) => value.parse().ok().map(ShaderDefValue::UInt),
+        _ => None,
+    }
+}
+
+/// Writes length-prefixed fields, so that values may contain any characters.
+#[derive(Default)]
+struct Encoder(String);
+
+impl Encoder {
+    fn field(&mut self, value: &str) {
+        self.0.push_str(&value.len().to_string());
+        self.0.push(':');
+        self.0.push_str(value);
+        self.0.push('\n');
+    }
+
+    fn list<T>(&mut self, values: &[T], mut encode: impl FnMut(&mut Self, &T)) {
+        self.field(&values.len().to_string());
+        for value in values {
+            encode(self, value);
+        }
+    }
+}
+
+/// Reads fields written by an [`Encoder`].
+struct Decoder<'a>(&'a str);
+
+impl<'a> Decoder<'a> {
+    fn field(&mut self) -> Option<&'a str> {
+        let (len, rest) = self.0.split_once(':')?;
+        let len = len.parse::<usize>().ok()?;
+        let value = rest.get(..len)?;
+        self.0 = rest.get(len..)?.strip_prefix('\n')?;
+        Some(value)
+    }
+
+    fn list<T>(&mut self, mut decode: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.field()?.parse::<usize>().ok()?;
+        (0..len).map(|_| decode(self)).collect()
+    }
+}
+
+/// Records of previously composed shaders, stored as a file per shader within a directory.
+///
+/// The cache is only an optimisation, so any record that can't be read is treated as missing and failing to write one
+/// is ignored.
+pub(crate) struct BuildCache {
+    dir: Option<PathBuf>,
+}
+
+impl BuildCache {
+    /// Creates a cache stored in the given directory, or a cache that never holds anything if there is none.
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    fn record_path(&self, shader: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{:016x}", hash(&shader))))
+    }
+
+    /// Gives the record stored for a shader, if it was made with the given key, none of the files the shader was
+    /// composed from have changed since and its imports would still resolve to the same files.
+    pub(crate) fn load(&self, shader: &str, key: u64) -> Option<Record> {
+        let encoded = std::fs::read_to_string(self.record_path(shader)?).ok()?;
+        let record = Record::decode(shader, &encoded)?;
+        let unchanged = record.key == key
+            && record
+                .files
+                .iter()
+                .all(|(path, hash)| hash_file(path).is_ok_and(|current| current == *hash))
+            && !record.missed_imports.iter().any(|path| path.is_file());
+
+        unchanged.then_some(record)
+    }
+
+    /// Stores the record for a shader, replacing any previous record.
+    pub(crate) fn store(&self, shader: &str, record: &Record) {
+        let Some(path) = self.record_path(shader) else {
+            return;
+        };
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = std::fs::write(path, record.encode(shader));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(files: Vec<(PathBuf, u64)>) -> Record {
+        Record {
+            key: 42,
+            files,
+            missed_imports: vec![PathBuf::from("/project/missing.wgsl")],
+            imports: vec![(
+                PathBuf::from("/project/lib.wgsl"),
+                vec![
+                    vec![],
+                    vec![
+                        ("A".to_owned(), ShaderDefValue::Bool(true)),
+                        ("B".to_owned(), ShaderDefValue::Int(-3)),
+                        ("C".to_owned(), ShaderDefValue::UInt(7)),
+                    ],
+                ],
+            )],
+            vertex_inputs: vec![(PathBuf::from("/project/lib.wgsl"), "Vertex".to_owned())],
+            exported_structs: vec!["Light".to_owned(), "Material".to_owned()],
+            renames: vec![("Light".to_owned(), "PointLight".to_owned())],
+            context: 7,
+            tokens: "pub mod a { }\n12:with: colons\n\né".to_owned(),
+        }
+    }
+
+    #[test]
+    fn hashes_are_fnv1a() {
+        let hash_bytes = |bytes: &[u8]| {
+            let mut hasher = StableHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+
+        assert_eq!(hash_bytes(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash_bytes(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash_bytes(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let record = record(vec![(PathBuf::from("/project/main.wgsl"), 1)]);
+        let encoded = record.encode("main.wgsl");
+
+        assert_eq!(Record::decode("main.wgsl", &encoded), Some(record.clone()));
+
+        let empty = Record {
+            files: record.files.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Record::decode("main.wgsl", &empty.encode("main.wgsl")),
+            Some(empty)
+        );
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let record = record(vec![(PathBuf::from("/project/main.wgsl"), 1)]);
+        let encoded = record.encode("main.wgsl");
+
+        assert_eq!(Record::decode("other.wgsl", &encoded), None);
+        assert_eq!(
+            Record::decode("main.wgsl", &encoded[..encoded.len() - 1]),
+            None
+        );
+        assert_eq!(
+            Record::decode("main.wgsl", &(encoded.clone() + "1:x\n")),
+            None
+        );
+        assert_eq!(
+            Record::decode(
+                "main.wgsl",
+                &encoded.replacen(CACHE_HEADER, "other header", 1)
+            ),
+            None
+        );
+        // A record without the shader's own file is never written
+        assert_eq!(
+            Record::decode("main.wgsl", &Record::default().encode("main.wgsl")),
+            None
+        );
+    }
+
+    #[test]
+    fn records_are_invalidated() {
+        let project = tempfile::tempdir().unwrap();
+        let main = project.path().join("main.wgsl");
+        let missed = project.path().join("lib.wgsl");
+        std::fs::write(&main, "fn main() {}").unwrap();
+        let cache = BuildCache::new(Some(project.path().join("cache")));
+        let record = Record {
+            missed_imports: vec![missed.clone()],
+            ..record(vec![(main.clone(), hash_file(&main).unwrap())])
+        };
+        cache.store("main.wgsl", &record);
+
+        assert_eq!(cache.load("main.wgsl", 42), Some(record));
+        // By key
+        assert_eq!(cache.load("main.wgsl", 43), None);
+        // By an import now resolving to another file
+        std::fs::write(&missed, "").unwrap();
+        assert_eq!(cache.load("main.wgsl", 42), None);
+        std::fs::remove_file(&missed).unwrap();
+        assert!(cache.load("main.wgsl", 42).is_some());
+        // By changed content
+        std::fs::write(&main, "fn main() { }").unwrap();
+        assert_eq!(cache.load("main.wgsl", 42), None);
+        // By a removed file
+        std::fs::remove_file(&main).unwrap();
+        assert_eq!(cache.load("main.wgsl", 42), None);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
+use naga_oil::compose::{ComposableModuleDescriptor, Composer, ComposerError, ShaderLanguage};
+
+/// A naga_oil `Composer` shared between entrypoints, which only adds a composable module again if it has changed
+/// since it was last added, so that libraries imported by many entrypoints are parsed once.
+///
+/// Composable modules are added without any shader defs. naga_oil instead builds each module for the defs given
+/// to the entrypoint importing it, caching a build for each distinct set of the defs that the module refers to, so
+/// one entrypoint's defs never affect another's.
+pub(crate) struct CachingComposer {
+    composer: Composer,
+    /// The file path and processed source of each composable module added, by module name.
+    added: HashMap<String, (String, String)>,
+}
+
+impl CachingComposer {
+    pub(crate) fn new(composer: Composer) -> Self {
+        Self {
+            composer,
+            added: HashMap::new(),
+        }
+    }
+
+    /// Replaces the composer with a new one, with the same configuration but no modules added.
+    ///
+    /// naga_oil numbers `@binding(auto)` bindings with a counter kept for the lifetime of the composer, so a shader
+    /// using them only gets the same bindings regardless of which shaders were composed before it with a new one.
+    pub(crate) fn reset(&mut self) {
+        let mut composer = Composer::default().with_capabilities(self.composer.capabilities);
+        composer.validate = self.composer.validate;
+        self.composer = composer;
+        self.added.clear();
+    }
+
+    /// Adds a WGSL composable module under the given name, unless it was already added with the same file path and
+    /// source and is still present.
+    pub(crate) fn add_composable_module(
+        &mut self,
+        name: &str,
+        file_path: &str,
+        source: &str,
+    ) -> Result<(), Box<ComposerError>> {
+        let unchanged = self
+            .added
+            .get(name)
+            .is_some_and(|(added_path, added_source)| {
+                added_path == file_path && added_source == source
+            });
+        // Replacing a module also removes every module that imports it
+        if unchanged && self.composer.contains_module(name) {
+            return Ok(());
+        }
+
+        self.added.remove(name);
+        self.composer
+            .add_composable_module(ComposableModuleDescriptor {
+                source,
+                file_path,
+                language: ShaderLanguage::Wgsl,
+                as_name: Some(name.to_owned()),
+                additional_imports: &[],
+                shader_defs: HashMap::new(),
+            })
+            .map_err(Box::new)?;
+        self.added
+            .insert(name.to_owned(), (file_path.to_owned(), source.to_owned()));
+
+        Ok(())
+    }
+}
+
+impl Deref for CachingComposer {
+    type Target = Composer;
+
+    fn deref(&self) -> &Composer {
+        &self.composer
+    }
+}
+
+impl DerefMut for CachingComposer {
+    fn deref_mut(&mut self) -> &mut Composer {
+        &mut self.composer
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use naga_oil::compose::ShaderDefValue;
+
+/// Converts an arbitrary string into a form usable within a shader def name, e.g. `x86_64` to `X86_64`.
+fn def_name_part(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Gives a `FEATURE_<NAME>` def for every cargo feature enabled on the crate running the build script.
+pub(crate) fn cargo_feature_defs() -> HashMap<String, ShaderDefValue> {
+    std::env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some((
+                format!("FEATURE_{}", def_name_part(feature)),
+                ShaderDefValue::Bool(true),
+            ))
+        })
+        .collect()
+}
+
+/// Gives defs describing the compilation target, as seen by the build script, e.g. `TARGET_OS_LINUX`,
+/// `TARGET_ARCH_X86_64`, `TARGET_FAMILY_UNIX` and `TARGET_POINTER_WIDTH`.
+pub(crate) fn target_defs() -> HashMap<String, ShaderDefValue> {
+    let mut defs = HashMap::new();
+
+    for (var, prefix) in [
+        ("CARGO_CFG_TARGET_OS", "TARGET_OS"),
+        ("CARGO_CFG_TARGET_ARCH", "TARGET_ARCH"),
+        ("CARGO_CFG_TARGET_FAMILY", "TARGET_FAMILY"),
+    ] {
+        let Ok(values) = std::env::var(var) else {
+            continue;
+        };
+        // Some cfgs, such as `target_family`, may hold several comma separated values
+        for value in values.split(',').filter(|value| !value.is_empty()) {
+            defs.insert(
+                format!("{}_{}", prefix, def_name_part(value)),
+                ShaderDefValue::Bool(true),
+            );
+        }
+    }
+
+    if let Some(width) = std::env::var("CARGO_CFG_TARGET_POINTER_WIDTH")
+        .ok()
+        .and_then(|width| width.parse().ok())
+    {
+        defs.insert(
+            "TARGET_POINTER_WIDTH".to_string(),
+            ShaderDefValue::UInt(width),
+        );
+    }
+
+    defs
+}
@@ -0,0 +1,97 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{error::GenerateError, lexer};
+
+lazy_static::lazy_static! {
+    static ref ENTRY_POINT_REGEX: Regex = Regex::new(r"@\s*(vertex|fragment|compute)\b").unwrap();
+}
+
+/// Gets the directory that a glob pattern searches within, being every leading path component without wildcards.
+pub(crate) fn glob_base_dir(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '[', '{'])
+        })
+        .collect()
+}
+
+/// Finds the `.wgsl` files matching a glob pattern relative to the project root, giving their paths relative to
+/// the project root in sorted order. If `require_entry_points` is set then files without any `@vertex`,
+/// `@fragment` or `@compute` entry points outside of comments are skipped.
+pub(crate) fn discover_entrypoints(
+    project_root: &Path,
+    pattern: &str,
+    require_entry_points: bool,
+) -> Result<Vec<String>, GenerateError> {
+    let full_pattern = project_root.join(pattern);
+    let paths = glob::glob(&full_pattern.to_string_lossy()).map_err(|e| GenerateError::Glob {
+        pattern: pattern.to_owned(),
+        message: e.to_string(),
+    })?;
+
+    let mut found = Vec::new();
+    for path in paths {
+        let path = path.map_err(|e| GenerateError::Io {
+            path: e.path().to_path_buf(),
+            error: e.into_error(),
+        })?;
+        if !path.is_file() || path.extension() != Some(OsStr::new("wgsl")) {
+            continue;
+        }
+
+        if require_entry_points {
+            let source = std::fs::read_to_string(&path).map_err(|error| GenerateError::Io {
+                path: path.clone(),
+                error,
+            })?;
+            // Commented out entry points don't count
+            if !ENTRY_POINT_REGEX.is_match(&lexer::strip_comments(&source)) {
+                continue;
+            }
+        }
+
+        let relative = path.strip_prefix(project_root).unwrap_or(&path);
+        found.push(relative.to_string_lossy().replace('\\', "/"));
+    }
+    found.sort();
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commented_out_entry_points_are_not_required_entry_points() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, source) in [
+            ("shader.wgsl", "@compute @workgroup_size(1)\nfn main() {}\n"),
+            (
+                "library.wgsl",
+                "// @compute @workgroup_size(1)\n/* @vertex */ fn helper() {}\n",
+            ),
+            ("notes.txt", "@compute"),
+        ] {
+            std::fs::write(dir.path().join(name), source).unwrap();
+        }
+
+        assert_eq!(
+            discover_entrypoints(dir.path(), "*", false).unwrap(),
+            ["library.wgsl", "shader.wgsl"]
+        );
+        assert_eq!(
+            discover_entrypoints(dir.path(), "*", true).unwrap(),
+            ["shader.wgsl"]
+        );
+    }
+}
@@ -1,12 +1,18 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Display, path::PathBuf};
 
-use naga_oil::compose::{Composer, ComposerError, ComposerErrorInner};
-use regex::Regex;
+use naga::{valid::ValidationError, Span, WithSpan};
+use naga_oil::compose::{Composer, ComposerError, ComposerErrorInner, ErrSource};
+use regex::{Captures, Regex};
 
 lazy_static::lazy_static! {
     static ref UNDECORATE_REGEX: Regex = Regex::new("(.+)X_naga_oil_mod_X([A-Z0-9]*)X").unwrap();
+    static ref UNDECORATE_ALL_REGEX: Regex = Regex::new("([A-Za-z0-9_]+?)X_naga_oil_mod_X([A-Z0-9]*)X").unwrap();
 }
 
+/// The number of low bits of a span offset that `naga_oil` uses for the offset within a module, with the
+/// remaining high bits giving the index of the module that the span is within.
+const SPAN_SHIFT: usize = 21;
+
 pub(crate) fn decompose_mangled_name(source: &str) -> Option<(String, &str)> {
     let captures = UNDECORATE_REGEX.captures(source)?;
     let name = captures.get(1).unwrap().as_str();
@@ -20,69 +26,277 @@ pub(crate) fn decompose_mangled_name(source: &str) -> Option<(String, &str)> {
     Some((module, name))
 }
 
-pub(crate) fn demangle_mod_names(source: &str, pad: bool) -> Cow<'_, str> {
-    let Some((module, name)) = decompose_mangled_name(source) else {
-        return std::borrow::Cow::Borrowed(source);
-    };
-
-    Cow::Owned(if pad {
-        let original_len = source.len();
-        format!(
-            "{module:>len$}::{name}",
-            len = original_len - 2 - name.len()
-        )
-    } else {
-        format!("{module}::{name}")
+/// Demangles every `naga_oil` decorated name within some text, such as an error message. With `pad`, each name is
+/// padded to the length of the decorated name, so that the positions of anything after it are unchanged.
+pub(crate) fn demangle_all_mod_names(source: &str, pad: bool) -> Cow<'_, str> {
+    UNDECORATE_ALL_REGEX.replace_all(source, |captures: &Captures<'_>| {
+        let full = captures.get(0).unwrap().as_str();
+        match decompose_mangled_name(full) {
+            Some((module, name)) if pad => format!(
+                "{module:>len$}::{name}",
+                len = full.len().saturating_sub(2 + name.len())
+            ),
+            Some((module, name)) => format!("{module}::{name}"),
+            None => full.to_owned(),
+        }
     })
 }
 
-pub(crate) fn format_compose_error(e: ComposerError, composer: &Composer) -> String {
-    let (source_name, source, offset) = match &e.source {
-        naga_oil::compose::ErrSource::Module {
-            name,
-            offset,
-            defs: _,
-        } => {
-            let source = composer
-                .module_sets
-                .get(name)
-                .unwrap_or_else(|| {
-                    panic!(
-                        "while handling error could not find module {}: {:?}",
-                        name, e
-                    )
-                })
-                .sanitized_source
-                .clone();
-            (name, source, *offset)
+/// Gives the index of the module that a validation error originated in. As with naga_oil, the last span is taken to
+/// be the most specific.
+pub(crate) fn validation_error_module(e: &WithSpan<ValidationError>) -> usize {
+    e.spans()
+        .last()
+        .and_then(|(span, _)| span.to_range())
+        .map(|range| range.start >> SPAN_SHIFT)
+        .unwrap_or(0)
+}
+
+/// Renders a validation error against the source of a single file, dropping the module index from spans.
+///
+/// naga_oil parses each file after a header declaring the items it imports, so spans are offset by `source_offset`,
+/// the length of that header, which is removed as in naga_oil's own errors.
+fn emit_validation_error(
+    e: &WithSpan<ValidationError>,
+    path: &str,
+    source: &str,
+    source_offset: usize,
+) -> String {
+    let clamp = |offset: usize| {
+        let mut offset = (offset & ((1 << SPAN_SHIFT) - 1))
+            .saturating_sub(source_offset)
+            .min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
         }
-        naga_oil::compose::ErrSource::Constructing {
-            source,
-            path,
-            offset,
-        } => (path, source.clone(), *offset),
+        offset as u32
     };
 
-    let source = " ".repeat(offset) + &source;
+    let mapped = e.spans().fold(
+        WithSpan::new(e.as_inner().clone()),
+        |mapped, (span, desc)| match span.to_range() {
+            Some(range) => mapped.with_span(Span::new(clamp(range.start), clamp(range.end)), desc),
+            None => mapped,
+        },
+    );
+
+    demangle_all_mod_names(&mapped.emit_to_string_with_path(source, path), false).into_owned()
+}
+
+/// Formats an error from validating a module built by `composer`, against the file that the error originated in as
+/// given by `err_source`.
+pub(crate) fn format_validation_error(
+    e: &WithSpan<ValidationError>,
+    composer: &Composer,
+    err_source: &ErrSource,
+) -> String {
+    let module_index = validation_error_module(e);
+
+    // Only spans within the originating file can be shown against its source
+    let filtered = e
+        .spans()
+        .filter(|(span, _)| {
+            span.to_range()
+                .is_some_and(|range| range.start >> SPAN_SHIFT == module_index)
+        })
+        .fold(WithSpan::new(e.as_inner().clone()), |filtered, context| {
+            filtered.with_context(context.clone())
+        });
+
+    emit_validation_error(
+        &filtered,
+        err_source.path(composer),
+        &err_source.source(composer),
+        err_source.offset(),
+    )
+}
+
+pub(crate) fn format_compose_error(e: ComposerError, composer: &Composer) -> String {
+    if let ErrSource::Module { name, .. } = &e.source {
+        if !composer.module_sets.contains_key(name) {
+            // Without the module there is no source to show the error against
+            return format!(
+                "{} (in module `{}`, which could not be found to show the error against)",
+                demangle_all_mod_names(&e.to_string(), false),
+                name
+            );
+        }
+    }
+    // As with naga_oil, errors are shown against the source that it parsed, after preprocessing
+    let source_name = e.source.path(composer).clone();
+    let source = e.source.source(composer).into_owned();
+    let offset = e.source.offset();
 
     match e.inner {
         ComposerErrorInner::WgslParseError(e) => {
-            let wgsl_error = e.emit_to_string_with_path(&source, source_name);
+            // Parse errors can't be remapped, so the source is padded to where naga_oil parsed it from instead
+            let source = " ".repeat(offset) + &source;
+            let wgsl_error = e.emit_to_string_with_path(&source, &source_name);
 
             // Demangle first line that probably contains type but not in context, so no padding required
-            let (first_line, other_lines) = wgsl_error.split_once('\n').unwrap();
-            let first_line = demangle_mod_names(first_line, false);
+            let (first_line, other_lines) =
+                wgsl_error.split_once('\n').unwrap_or((&wgsl_error, ""));
+            let first_line = demangle_all_mod_names(first_line, false);
 
             // Demangle anything else
-            let other_lines = demangle_mod_names(other_lines, true);
+            let other_lines = demangle_all_mod_names(other_lines, true);
 
             format!("wgsl parsing error: {}\n{}", first_line, other_lines)
         }
         ComposerErrorInner::GlslParseError(e) => format!("glsl parsing error(s): {:?}", e),
         ComposerErrorInner::ShaderValidationError(e) => format!(
             "failed to build a valid final module: {0}",
-            e.emit_to_string(&source)
+            emit_validation_error(&e, &source_name, &source, offset)
         ),
         _ => format!("{}", e),
     }
 }
+
+/// An error encountered while generating code for a shader.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum GenerateError {
+    /// A file could not be read or written.
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// An environment variable that cargo sets for build scripts could not be read, so a path depending on it was
+    /// unknown.
+    Env {
+        variable: String,
+        error: std::env::VarError,
+    },
+    /// A glob pattern given to find entrypoints was invalid.
+    Glob { pattern: String, message: String },
+    /// An entrypoint or one of its imports could not be found.
+    ImportResolution { shader: String, message: String },
+    /// The imports of a shader form a cycle, given as the files visited in order.
+    Cycle { shader: String, cycle: Vec<PathBuf> },
+    /// `naga_oil` failed to compose a shader.
+    Compose { shader: String, message: String },
+    /// A composed shader failed validation.
+    Validation { shader: String, message: String },
+    /// Rust code could not be generated for a shader.
+    Codegen { shader: String, message: String },
+}
+
+impl GenerateError {
+    /// Gets the entrypoint that this error was encountered while processing, if any.
+    pub fn shader(&self) -> Option<&str> {
+        match self {
+            GenerateError::Io { .. } | GenerateError::Env { .. } | GenerateError::Glob { .. } => {
+                None
+            }
+            GenerateError::ImportResolution { shader, .. }
+            | GenerateError::Cycle { shader, .. }
+            | GenerateError::Compose { shader, .. }
+            | GenerateError::Validation { shader, .. }
+            | GenerateError::Codegen { shader, .. } => Some(shader),
+        }
+    }
+}
+
+impl Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::Io { path, error } => {
+                write!(f, "could not access `{}`: {}", path.display(), error)
+            }
+            GenerateError::Env { variable, error } => {
+                write!(
+                    f,
+                    "could not read `{}`, which cargo sets when running build scripts: {}",
+                    variable, error
+                )
+            }
+            GenerateError::Glob { pattern, message } => {
+                write!(f, "invalid glob pattern `{}`: {}", pattern, message)
+            }
+            GenerateError::ImportResolution { shader, message } => {
+                write!(f, "in shader `{}`: {}", shader, message)
+            }
+            GenerateError::Cycle { shader, cycle } => {
+                writeln!(f, "in shader `{}`: found import cycle:", shader)?;
+                for path in cycle {
+                    writeln!(f, "`{}` ->", path.display())?;
+                }
+                match cycle.first() {
+                    Some(first) => write!(f, "`{}`", first.display()),
+                    None => Ok(()),
+                }
+            }
+            GenerateError::Compose { shader, message } => {
+                write!(f, "failed to compose shader `{}`: {}", shader, message)
+            }
+            GenerateError::Validation { shader, message } => {
+                write!(f, "failed to validate shader `{}`: {}", shader, message)
+            }
+            GenerateError::Codegen { shader, message } => {
+                write!(
+                    f,
+                    "failed to generate code for shader `{}`: {}",
+                    shader, message
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GenerateError::Io { error, .. } => Some(error),
+            GenerateError::Env { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Every error encountered during a run of [`Generator`](crate::Generator). Each build of a shader reports at most one
+/// error, so an entrypoint composed with permutations of shader defs may report one for each permutation.
+#[derive(Debug)]
+pub struct GenerateErrors {
+    errors: Vec<GenerateError>,
+}
+
+impl GenerateErrors {
+    pub(crate) fn new(errors: Vec<GenerateError>) -> Self {
+        assert!(!errors.is_empty(), "at least one error must be given");
+        Self { errors }
+    }
+
+    /// Gets every error encountered.
+    pub fn errors(&self) -> &[GenerateError] {
+        &self.errors
+    }
+}
+
+impl From<GenerateError> for GenerateErrors {
+    fn from(error: GenerateError) -> Self {
+        Self::new(vec![error])
+    }
+}
+
+impl IntoIterator for GenerateErrors {
+    type Item = GenerateError;
+    type IntoIter = std::vec::IntoIter<GenerateError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
+    }
+}
+
+impl Display for GenerateErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GenerateErrors {}
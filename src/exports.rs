@@ -1,28 +1,661 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use regex::Regex;
+use syn::{ext::IdentExt, parse::Parser, punctuated::Punctuated, visit_mut::VisitMut};
+
+use crate::lexer;
 
 lazy_static::lazy_static! {
-    static ref EXPORT_STRUCT_REGEX: Regex = Regex::new(r"@export\s+struct\s+([^\s]+)").unwrap();
+    static ref EXPORT_REGEX: Regex = Regex::new(
+        r#"(@export(?:\s*\(((?:"[^"]*"|[^)"])*)\))?)\s+(?:@\w+\s*(?:\([^)]*\))?\s*)*(struct|const|override|fn|var)\b\s*(?:<[^>]*>)?\s*([A-Za-z_][A-Za-z0-9_]*)"#
+    )
+    .unwrap();
+    static ref EXPORT_OPTION_REGEX: Regex = Regex::new(r#"^\s*(\w+)\s*=\s*"([^"]*)"\s*(?:,|$)"#).unwrap();
+}
+
+/// The name of the module that `naga-to-tokenstream` places exported types within.
+pub(crate) const TYPES_MODULE_NAME: &str = "types";
+/// The name of the module that `naga-to-tokenstream` places constants within.
+const CONSTANTS_MODULE_NAME: &str = "constants";
+/// The name of the module that `naga-to-tokenstream` places overrides within.
+const OVERRIDES_MODULE_NAME: &str = "overrides";
+/// The name of the module that `naga-to-tokenstream` places entry points within.
+const ENTRY_POINTS_MODULE_NAME: &str = "entry_points";
+/// The name of the module that `naga-to-tokenstream` places global variables within.
+const GLOBALS_MODULE_NAME: &str = "globals";
+
+/// Options given to an `@export(...)` attribute, e.g. `@export(name = "GpuFoo", vis = "pub(crate)")`.
+/// These only affect exported structs.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub(crate) struct ExportOptions {
+    /// The name to give the generated Rust struct.
+    pub(crate) name: Option<String>,
+    /// Extra traits for the generated Rust struct to derive, separated by commas.
+    pub(crate) derive: Option<String>,
+    /// The visibility of the generated Rust struct.
+    pub(crate) vis: Option<String>,
+    /// Whether a vertex input struct is stepped per `vertex` or per `instance`.
+    pub(crate) step_mode: Option<String>,
+}
+
+impl ExportOptions {
+    /// Parses a comma separated list of `option = "value"` pairs.
+    fn parse(mut args: &str) -> Result<Self, String> {
+        let mut options = Self::default();
+        while !args.trim().is_empty() {
+            let option = EXPORT_OPTION_REGEX.captures(args).ok_or_else(|| {
+                format!(
+                    "expected options of the form `option = \"value\"`, found `{}`",
+                    args.trim()
+                )
+            })?;
+            args = &args[option.get(0).unwrap().end()..];
+
+            let name = option.get(1).unwrap().as_str();
+            let value = Some(option.get(2).unwrap().as_str().to_owned());
+            let field = match name {
+                "name" => &mut options.name,
+                "derive" => &mut options.derive,
+                "vis" => &mut options.vis,
+                "step_mode" => &mut options.step_mode,
+                unknown => {
+                    return Err(format!(
+                    "unknown option `{}` - expected one of `name`, `derive`, `vis` or `step_mode`",
+                    unknown
+                ))
+                }
+            };
+            if field.is_some() {
+                return Err(format!("option `{}` is given more than once", name));
+            }
+            *field = value;
+        }
+        Ok(options)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 #[non_exhaustive]
 pub(crate) enum Export {
-    Struct { struct_name: String },
+    Struct {
+        struct_name: String,
+        options: ExportOptions,
+    },
+    Constant {
+        name: String,
+    },
+    Override {
+        name: String,
+    },
+    Function {
+        name: String,
+    },
+    GlobalVariable {
+        name: String,
+    },
 }
 
 /// Removes `@export` statements, replacing them with an equivalent number of spaces so as to not disrupt spans.
-pub(crate) fn strip_exports(source: &str) -> (String, HashSet<Export>) {
+/// Anything within comments is left alone.
+pub(crate) fn strip_exports(source: &str) -> String {
+    // Comments are blanked without moving anything else, so matches in the stripped source are at the same positions
+    let uncommented = lexer::strip_comments(source);
+    let mut stripped = String::with_capacity(source.len());
+    let mut copied = 0;
+    for group in EXPORT_REGEX.captures_iter(&uncommented) {
+        // Blank out the attribute byte for byte, keeping any newlines within its arguments
+        let attribute = group.get(1).unwrap().range();
+        stripped.push_str(&source[copied..attribute.start]);
+        for c in source[attribute.clone()].chars() {
+            match c {
+                '\n' => stripped.push('\n'),
+                c => stripped.push_str(&" ".repeat(c.len_utf8())),
+            }
+        }
+        copied = attribute.end;
+    }
+    stripped.push_str(&source[copied..]);
+
+    stripped
+}
+
+/// Finds the items marked with `@export` in some source, along with any options given to them. Anything within
+/// comments is ignored.
+pub(crate) fn parse_exports(source: &str) -> Result<HashSet<Export>, String> {
     let mut exports = HashSet::new();
+    let source = &lexer::strip_comments(source);
+
+    for group in EXPORT_REGEX.captures_iter(source) {
+        let name = group.get(4).unwrap().as_str().to_owned();
+        let kind = group.get(3).unwrap().as_str();
+        let error = |message: String| {
+            let line = source[..group.get(0).unwrap().start()]
+                .matches('\n')
+                .count()
+                + 1;
+            format!(
+                "invalid `@export` of `{}` on line {}: {}",
+                name, line, message
+            )
+        };
+
+        let options = match group.get(2) {
+            Some(args) if kind != "struct" && !args.as_str().trim().is_empty() => {
+                return Err(error("only exported structs take options".to_owned()));
+            }
+            Some(args) => ExportOptions::parse(args.as_str()).map_err(error)?,
+            None => ExportOptions::default(),
+        };
+        exports.insert(match kind {
+            "struct" => Export::Struct {
+                struct_name: name,
+                options,
+            },
+            "const" => Export::Constant { name },
+            "override" => Export::Override { name },
+            "fn" => Export::Function { name },
+            "var" => Export::GlobalVariable { name },
+            _ => unreachable!("regex only matches the above keywords"),
+        });
+    }
+
+    Ok(exports)
+}
+
+/// Gives the names of the entry points of a module that are exported. Exported functions that aren't entry points of
+/// the module, such as entry points left out by shader defs, are left out.
+pub(crate) fn exported_entry_points<'a>(
+    exports: &'a HashSet<Export>,
+    module: &naga::Module,
+) -> HashSet<&'a str> {
+    exports
+        .iter()
+        .filter_map(|export| match export {
+            Export::Function { name } => Some(name.as_str()),
+            _ => None,
+        })
+        .filter(|name| {
+            module
+                .entry_points
+                .iter()
+                .any(|entry_point| entry_point.name == *name)
+        })
+        .collect()
+}
+
+/// Checks that no function other than an entry point is exported, as nothing is generated for other functions.
+pub(crate) fn check_exported_functions(
+    exports: &HashSet<Export>,
+    module: &naga::Module,
+) -> Result<(), String> {
+    let mut helpers = exports
+        .iter()
+        .filter_map(|export| match export {
+            Export::Function { name } => Some(name.as_str()),
+            _ => None,
+        })
+        .filter(|name| {
+            module
+                .functions
+                .iter()
+                .any(|(_, function)| function.name.as_deref() == Some(*name))
+        })
+        .collect::<Vec<_>>();
+    helpers.sort();
+
+    match helpers.first() {
+        Some(name) => Err(format!(
+            "`{}` is exported but is not an entry point - only entry points can be exported with `@export fn`",
+            name
+        )),
+        None => Ok(()),
+    }
+}
 
-    let new_src = EXPORT_STRUCT_REGEX.replace_all(source, |group: &regex::Captures<'_>| {
-        let name = group.get(1).unwrap().as_str();
-        exports.insert(Export::Struct {
-            struct_name: name.to_owned(),
+/// Removes any non-struct items that were not exported from the items generated by `naga-to-tokenstream`.
+///
+/// Each kind of item is only filtered if at least one item of that kind is exported, so that files without
+/// any `@export`s on constants, overrides, entry points or variables keep all of them.
+pub(crate) fn filter_exported_items(
+    items: &mut [syn::Item],
+    exports: &HashSet<Export>,
+    module: &naga::Module,
+) {
+    let mut filters: [(&str, HashSet<&str>); 4] = [
+        (CONSTANTS_MODULE_NAME, HashSet::new()),
+        (OVERRIDES_MODULE_NAME, HashSet::new()),
+        (
+            ENTRY_POINTS_MODULE_NAME,
+            exported_entry_points(exports, module),
+        ),
+        (GLOBALS_MODULE_NAME, HashSet::new()),
+    ];
+    for export in exports {
+        let (i, name) = match export {
+            Export::Struct { .. } | Export::Function { .. } => continue,
+            Export::Constant { name } => (0, name),
+            Export::Override { name } => (1, name),
+            Export::GlobalVariable { name } => (3, name),
+        };
+        filters[i].1.insert(name);
+    }
+
+    for item in items {
+        let syn::Item::Mod(syn::ItemMod {
+            ident,
+            content: Some((_, content)),
+            ..
+        }) = item
+        else {
+            continue;
+        };
+        let Some((_, names)) = filters
+            .iter()
+            .find(|(module_name, names)| ident == module_name && !names.is_empty())
+        else {
+            continue;
+        };
+
+        content.retain(|item| match item_ident(item) {
+            // Keywords are generated as raw identifiers, but named without the `r#` in the shader
+            Some(ident) => names.contains(ident.unraw().to_string().as_str()),
+            // Anything unnamed, such as a `use`, is kept
+            None => true,
         });
-        group.get(0).unwrap().as_str().replace("@export", "       ")
-    });
+    }
+}
+
+fn item_ident(item: &syn::Item) -> Option<&syn::Ident> {
+    match item {
+        syn::Item::Const(item) => Some(&item.ident),
+        syn::Item::Fn(item) => Some(&item.sig.ident),
+        syn::Item::Mod(item) => Some(&item.ident),
+        syn::Item::Static(item) => Some(&item.ident),
+        syn::Item::Struct(item) => Some(&item.ident),
+        syn::Item::Type(item) => Some(&item.ident),
+        _ => None,
+    }
+}
+
+/// Gives the names of exported structs that have been given a different Rust name, from shader name to Rust name.
+pub(crate) fn renamed_structs(exports: &HashSet<Export>) -> impl Iterator<Item = (&str, &str)> {
+    exports.iter().filter_map(|export| match export {
+        Export::Struct {
+            struct_name,
+            options: ExportOptions {
+                name: Some(name), ..
+            },
+        } => Some((struct_name.as_str(), name.as_str())),
+        _ => None,
+    })
+}
+
+/// Applies the options given to `@export(...)` attributes to the structs generated by `naga-to-tokenstream`.
+pub(crate) fn apply_export_options(
+    items: &mut [syn::Item],
+    exports: &HashSet<Export>,
+) -> Result<(), String> {
+    let mut renames = HashMap::new();
+
+    for export in exports {
+        let Export::Struct {
+            struct_name,
+            options,
+        } = export
+        else {
+            continue;
+        };
+        let Some(item) = find_struct(items, struct_name) else {
+            continue;
+        };
+
+        if let Some(vis) = &options.vis {
+            item.vis = syn::parse_str(vis)
+                .map_err(|e| format!("invalid `vis` for struct `{}`: {}", struct_name, e))?;
+        }
+        if let Some(derive) = &options.derive {
+            let derives = Punctuated::<syn::Path, syn::Token![,]>::parse_terminated
+                .parse_str(derive)
+                .map_err(|e| format!("invalid `derive` for struct `{}`: {}", struct_name, e))?;
+
+            // Traits are compared by name, as the generated derives may give their full paths
+            let mut derived = derived_traits(item);
+            let derives = derives
+                .into_iter()
+                .filter(|path| {
+                    path.segments
+                        .last()
+                        .is_some_and(|last| derived.insert(last.ident.to_string()))
+                })
+                .collect::<Vec<_>>();
+            if !derives.is_empty() {
+                item.attrs
+                    .push(syn::parse_quote! { #[derive(#(#derives),*)] });
+            }
+        }
+        if let Some(name) = &options.name {
+            let name = syn::parse_str::<syn::Ident>(name)
+                .map_err(|e| format!("invalid `name` for struct `{}`: {}", struct_name, e))?;
+            renames.insert(struct_name.clone(), name);
+        }
+    }
+
+    if !renames.is_empty() {
+        let mut renamer = StructRenamer {
+            renames,
+            modules: Vec::new(),
+        };
+        for item in items {
+            renamer.visit_item_mut(item);
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds a generated struct within the module of exported types.
+fn find_struct<'a>(items: &'a mut [syn::Item], name: &str) -> Option<&'a mut syn::ItemStruct> {
+    items.iter_mut().find_map(|item| match item {
+        syn::Item::Mod(syn::ItemMod {
+            ident,
+            content: Some((_, content)),
+            ..
+        }) if ident == TYPES_MODULE_NAME => content.iter_mut().find_map(|item| match item {
+            syn::Item::Struct(item) if item.ident == name => Some(item),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+/// Gives the names of the traits that a generated struct already derives.
+fn derived_traits(item: &syn::ItemStruct) -> HashSet<String> {
+    item.attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("derive"))
+        .filter_map(|attr| {
+            attr.parse_args_with(Punctuated::<syn::Path, syn::Token![,]>::parse_terminated)
+                .ok()
+        })
+        .flatten()
+        .filter_map(|path| Some(path.segments.last()?.ident.to_string()))
+        .collect()
+}
+
+/// Renames structs, along with every reference to them within the same generated module.
+struct StructRenamer {
+    renames: HashMap<String, syn::Ident>,
+    /// The modules entered within the generated module, innermost last.
+    modules: Vec<syn::Ident>,
+}
+
+impl StructRenamer {
+    /// Whether the module being visited is this module's types.
+    fn in_types(&self) -> bool {
+        matches!(self.modules.as_slice(), [module] if module == TYPES_MODULE_NAME)
+    }
+
+    /// Gives the position of the segment naming a struct, if the path refers to this module's types. Paths to types
+    /// of other modules, which are renamed when they are generated, and to anything else are left alone.
+    fn struct_segment(&self, path: &syn::Path) -> Option<usize> {
+        let segments = path
+            .segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect::<Vec<_>>();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+        match segments.as_slice() {
+            [_] if self.in_types() => Some(0),
+            ["self", _] if self.in_types() => Some(1),
+            [supers @ .., types, _]
+                if *types == TYPES_MODULE_NAME
+                    && supers.len() == self.modules.len()
+                    && supers.iter().all(|segment| *segment == "super") =>
+            {
+                Some(supers.len() + 1)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl VisitMut for StructRenamer {
+    fn visit_item_mod_mut(&mut self, item: &mut syn::ItemMod) {
+        self.modules.push(item.ident.clone());
+        syn::visit_mut::visit_item_mod_mut(self, item);
+        self.modules.pop();
+    }
+
+    fn visit_item_struct_mut(&mut self, item: &mut syn::ItemStruct) {
+        if self.in_types() {
+            if let Some(name) = self.renames.get(&item.ident.to_string()) {
+                item.ident = name.clone();
+            }
+        }
+        syn::visit_mut::visit_item_struct_mut(self, item);
+    }
+
+    fn visit_path_mut(&mut self, path: &mut syn::Path) {
+        if let Some(i) = self.struct_segment(path) {
+            let segment = &mut path.segments[i];
+            if let Some(name) = self.renames.get(&segment.ident.to_string()) {
+                segment.ident = name.clone();
+            }
+        }
+        syn::visit_mut::visit_path_mut(self, path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    /// Gives the names of the items left in each generated module after filtering.
+    fn filtered(source: &str, mut items: Vec<syn::Item>) -> Vec<(String, Vec<String>)> {
+        let exports = parse_exports(source).unwrap();
+        let module = naga::front::wgsl::parse_str(&strip_exports(source)).unwrap();
+        filter_exported_items(&mut items, &exports, &module);
+        items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Mod(syn::ItemMod {
+                    ident,
+                    content: Some((_, content)),
+                    ..
+                }) => Some((
+                    ident.to_string(),
+                    content
+                        .iter()
+                        .filter_map(item_ident)
+                        .map(ToString::to_string)
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    const SHADER: &str = "
+const A: u32 = 1u;
+const a: u32 = 2u;
+fn helper() -> u32 { return A + a; }
+@compute @workgroup_size(1) fn main() { let value = helper(); }
+@compute @workgroup_size(1) fn other() {}
+";
+
+    fn generated() -> Vec<syn::Item> {
+        vec![
+            parse_quote! { pub mod constants { pub const A: u32 = 1; pub const a: u32 = 2; } },
+            parse_quote! { pub mod entry_points { pub mod main {} pub mod other {} } },
+        ]
+    }
+
+    fn names(module: &str, items: &[&str]) -> (String, Vec<String>) {
+        (
+            module.to_owned(),
+            items.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    #[test]
+    fn names_are_matched_exactly() {
+        let source = SHADER.replace("const a", "@export const a");
+        assert_eq!(
+            filtered(&source, generated()),
+            [
+                names("constants", &["a"]),
+                names("entry_points", &["main", "other"])
+            ]
+        );
+    }
+
+    #[test]
+    fn exported_functions_filter_entry_points() {
+        let source = SHADER.replace("fn other", "@export fn other");
+        assert_eq!(
+            filtered(&source, generated()),
+            [
+                names("constants", &["A", "a"]),
+                names("entry_points", &["other"])
+            ]
+        );
+    }
+
+    #[test]
+    fn only_entry_points_are_exported_functions() {
+        let check = |source: &str| {
+            let exports = parse_exports(source).unwrap();
+            let module = naga::front::wgsl::parse_str(&strip_exports(source)).unwrap();
+            check_exported_functions(&exports, &module)
+        };
+
+        assert_eq!(
+            check(&SHADER.replace("fn other", "@export fn other")),
+            Ok(())
+        );
+        let error = check(&SHADER.replace("fn helper", "@export fn helper")).unwrap_err();
+        assert!(error.contains("`helper`"), "{}", error);
+    }
+
+    #[test]
+    fn commented_exports_are_ignored() {
+        let source = "
+// @export const A: u32 = 1u;
+/* @export
+   struct B { b: u32 } */
+@export /* @export(name = \"C\") */ struct D { d: u32 }
+";
+        assert_eq!(
+            parse_exports(source).unwrap(),
+            HashSet::from([Export::Struct {
+                struct_name: "D".to_owned(),
+                options: ExportOptions::default(),
+            }])
+        );
+
+        let stripped = strip_exports(source);
+        assert_eq!(stripped.len(), source.len());
+        assert_eq!(stripped.matches("@export").count(), 3);
+        assert!(stripped.contains("        /* @export(name = \"C\") */ struct D"));
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        assert_eq!(
+            ExportOptions::parse(r#" name = "GpuLight", derive = "Hash, PartialEq", "#).unwrap(),
+            ExportOptions {
+                name: Some("GpuLight".to_owned()),
+                derive: Some("Hash, PartialEq".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(ExportOptions::parse("").unwrap(), ExportOptions::default());
+    }
+
+    #[test]
+    fn malformed_options_are_rejected() {
+        for args in [
+            r#"name = GpuLight"#,
+            r#"name = "GpuLight" derive = "Hash""#,
+            r#"name = "A", name = "B""#,
+            r#"size = "4""#,
+            r#"name = "GpuLight", oops"#,
+        ] {
+            assert!(ExportOptions::parse(args).is_err(), "{}", args);
+        }
+    }
+
+    #[test]
+    fn only_structs_take_options() {
+        assert!(parse_exports(r#"@export(name = "B") const A: u32 = 1u;"#).is_err());
+        assert!(parse_exports("@export() const A: u32 = 1u;").is_ok());
+        let error = parse_exports("\n@export(nope) struct A { a: u32 }").unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+    }
+
+    fn apply(options: &str, mut items: Vec<syn::Item>) -> String {
+        let exports =
+            parse_exports(&format!("@export({}) struct A {{ a: u32 }}", options)).unwrap();
+        apply_export_options(&mut items, &exports).unwrap();
+        quote::quote! { #(#items)* }.to_string()
+    }
+
+    #[test]
+    fn renames_only_apply_to_this_modules_types() {
+        let generated = apply(
+            r#"name = "GpuA""#,
+            vec![
+                parse_quote! {
+                    pub mod types {
+                        pub struct A { pub a: u32 }
+                        pub struct B { pub a: A, pub other: super::super::super::lib::types::A }
+                    }
+                },
+                parse_quote! {
+                    pub mod globals {
+                        pub mod a {
+                            pub type Ty = super::super::types::A;
+                            pub type Other = A;
+                        }
+                    }
+                },
+            ],
+        );
+        let expected = quote::quote! {
+            pub mod types {
+                pub struct GpuA { pub a: u32 }
+                pub struct B { pub a: GpuA, pub other: super::super::super::lib::types::A }
+            }
+            pub mod globals {
+                pub mod a {
+                    pub type Ty = super::super::types::GpuA;
+                    pub type Other = A;
+                }
+            }
+        };
+        assert_eq!(generated, expected.to_string());
+    }
 
-    (new_src.into_owned(), exports)
+    #[test]
+    fn derives_are_not_repeated() {
+        let generated = apply(
+            r#"derive = "Clone, Hash, Hash, core::fmt::Debug""#,
+            vec![parse_quote! {
+                pub mod types {
+                    #[derive(Debug, ::core::clone::Clone)]
+                    pub struct A { pub a: u32 }
+                }
+            }],
+        );
+        let expected = quote::quote! {
+            pub mod types {
+                #[derive(Debug, ::core::clone::Clone)]
+                #[derive(Hash)]
+                pub struct A { pub a: u32 }
+            }
+        };
+        assert_eq!(generated, expected.to_string());
+    }
 }
@@ -0,0 +1,1457 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use naga::valid::{Capabilities, ValidationFlags};
+use naga_oil::compose::{Composer, ShaderDefValue};
+use quote::ToTokens;
+use syn::parse_quote;
+
+use crate::{
+    cache::{self, BuildCache, Defs, Record},
+    composer::CachingComposer,
+    defs, discovery,
+    error::{GenerateError, GenerateErrors},
+    exports::{self, Export, TYPES_MODULE_NAME},
+    layout::{self, ModuleLayout},
+    module::SearchPaths,
+    module_items, parallel,
+    source::Sourcecode,
+    vertex_input_types,
+};
+
+/// The name of the directory within `OUT_DIR` holding records of previously composed shaders.
+const CACHE_DIR_NAME: &str = "wgsl_oil_cache";
+
+/// The most defs an entrypoint may be permuted over, as every combination of them is composed.
+const MAX_PERMUTATION_DEFS: usize = 10;
+
+/// The name of a build of a shader, if it has permutations, along with the defs it is built with.
+type Variant = (Option<String>, HashMap<String, ShaderDefValue>);
+
+/// The state each thread composes and validates shaders with, as neither can be shared between threads.
+///
+/// Creating a composer is slow, so it is only done once a shader needs composing.
+struct Worker {
+    capabilities: Capabilities,
+    validation_flags: ValidationFlags,
+    state: Option<(CachingComposer, naga::valid::Validator)>,
+    /// Files that the shaders handled by this worker depend on, for cargo to watch.
+    watched: Vec<PathBuf>,
+}
+
+impl Worker {
+    fn new(capabilities: Capabilities, validation_flags: ValidationFlags) -> Self {
+        Self {
+            capabilities,
+            validation_flags,
+            state: None,
+            watched: Vec::new(),
+        }
+    }
+
+    /// Gives the composer and validator, creating them if this is the first shader to be composed.
+    fn get(&mut self) -> (&mut CachingComposer, &mut naga::valid::Validator) {
+        let (composer, validator) = self.state.get_or_insert_with(|| {
+            // Validation is done separately so that the configured validation flags are respected
+            (
+                CachingComposer::new(
+                    Composer::non_validating().with_capabilities(self.capabilities),
+                ),
+                naga::valid::Validator::new(self.validation_flags, self.capabilities),
+            )
+        });
+        (composer, validator)
+    }
+}
+
+/// How `#define` directives within imported files apply, as naga_oil itself only accepts them in top-level shaders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ImportedDefines {
+    /// Defines only apply within the file declaring them.
+    #[default]
+    Scoped,
+    /// Defines apply to the whole shader importing the file, as if they were given as shader defs. Files defining the
+    /// same name with different values are an error.
+    Exported,
+}
+
+/// A shader file to generate a Rust module for, along with any configuration specific to it.
+#[derive(Debug, Clone)]
+struct Entrypoint {
+    path: String,
+    shader_defs: HashMap<String, ShaderDefValue>,
+    /// Boolean defs to generate every combination of, each combination as its own module.
+    permutations: Vec<String>,
+}
+
+impl Entrypoint {
+    fn new(path: String) -> Self {
+        Self {
+            path,
+            shader_defs: HashMap::new(),
+            permutations: Vec::new(),
+        }
+    }
+
+    /// Gives every combination of shader defs that this entrypoint should be built with, named by the enabled defs.
+    fn variants(&self) -> Result<Vec<Variant>, GenerateError> {
+        if self.permutations.is_empty() {
+            return Ok(vec![(None, HashMap::new())]);
+        }
+        if self.permutations.len() > MAX_PERMUTATION_DEFS {
+            return Err(self.codegen_error(format!(
+                "{} defs were given to permute, but at most {} may be as every combination of them is composed",
+                self.permutations.len(),
+                MAX_PERMUTATION_DEFS,
+            )));
+        }
+
+        // Names are compared once made into identifiers, as that is what the generated modules are called
+        let mut names = HashMap::<String, Vec<&String>>::new();
+        let mut variants = Vec::new();
+        for mask in 0..1usize << self.permutations.len() {
+            let enabled = self
+                .permutations
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, def)| def)
+                .collect::<Vec<_>>();
+
+            let name = if enabled.is_empty() {
+                "base".to_owned()
+            } else {
+                enabled
+                    .iter()
+                    .map(|def| def.to_lowercase())
+                    .collect::<Vec<_>>()
+                    .join("_")
+            };
+            let ident = layout::sanitize_ident(&name);
+            if ident == TYPES_MODULE_NAME {
+                return Err(self.codegen_error(format!(
+                    "the permutation enabling {} would be generated as `{}`, which is the module of exported types",
+                    describe_defs(&enabled),
+                    ident,
+                )));
+            }
+            if let Some(other) = names.get(&ident) {
+                return Err(self.codegen_error(format!(
+                    "the permutations enabling {} and {} would both be generated as the module `{}`",
+                    describe_defs(other),
+                    describe_defs(&enabled),
+                    ident,
+                )));
+            }
+            names.insert(ident, enabled.clone());
+
+            let defs = enabled
+                .into_iter()
+                .map(|def| (def.clone(), ShaderDefValue::Bool(true)))
+                .collect();
+            variants.push((Some(name), defs));
+        }
+
+        Ok(variants)
+    }
+
+    fn codegen_error(&self, message: String) -> GenerateError {
+        GenerateError::Codegen {
+            shader: self.path.clone(),
+            message,
+        }
+    }
+}
+
+/// Lists permutation defs for an error message.
+fn describe_defs(defs: &[&String]) -> String {
+    if defs.is_empty() {
+        return "no defs".to_owned();
+    }
+    defs.iter()
+        .map(|def| format!("`{}`", def))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A single composed and validated build of an entrypoint.
+pub(crate) struct Composed {
+    /// The name of the permutation this was built as, if the entrypoint has permutations.
+    pub(crate) variant: Option<String>,
+    pub(crate) sourcecode: Sourcecode,
+    pub(crate) module: naga::Module,
+    pub(crate) info: naga::valid::ModuleInfo,
+}
+
+/// A shader to generate a module for, either composed during this run or as recorded by a previous run.
+struct Shader {
+    path: String,
+    module_name: String,
+    /// Every build of the shader, so that it can be composed again if needed.
+    variants: Vec<Variant>,
+    record: Record,
+    /// The builds of the shader, if it was composed during this run rather than loaded from the cache.
+    builds: Option<Vec<Composed>>,
+}
+
+/// Configures and runs code generation for a set of WGSL entrypoints.
+///
+/// Intended to be used from a build script:
+///
+/// ```rust ignore
+/// generate_wgsl_oil::Generator::new()
+///     .entrypoint("src/shaders/main.wgsl")
+///     .shader_def("MAX_LIGHTS", ShaderDefValue::UInt(16))
+///     .write_to(std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("shaders.rs"))
+///     .unwrap_or_else(|errors| panic!("{errors}"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Generator {
+    project_root: Option<PathBuf>,
+    import_paths: Vec<PathBuf>,
+    import_roots: BTreeMap<String, PathBuf>,
+    entrypoints: Vec<Entrypoint>,
+    entrypoint_globs: Vec<String>,
+    globs_require_entry_points: bool,
+    nested_modules: bool,
+    threads: Option<usize>,
+    cache: bool,
+    capabilities: Capabilities,
+    validation_flags: ValidationFlags,
+    shader_defs: HashMap<String, ShaderDefValue>,
+    cargo_feature_defs: bool,
+    target_defs: bool,
+    imported_defines: ImportedDefines,
+    pub(crate) gen_bytemuck: bool,
+    pub(crate) gen_glam: bool,
+    pub(crate) gen_encase: bool,
+    pub(crate) gen_naga: bool,
+    pub(crate) gen_wgpu: bool,
+    #[cfg(feature = "naga")]
+    pub(crate) embed_naga_module: bool,
+    #[cfg(feature = "naga")]
+    pub(crate) gen_wgpu_naga_ir: bool,
+    #[cfg(feature = "spirv")]
+    pub(crate) gen_spirv: bool,
+    #[cfg(feature = "msl")]
+    pub(crate) gen_msl: bool,
+    #[cfg(feature = "msl")]
+    pub(crate) msl_options: naga::back::msl::Options,
+    #[cfg(feature = "hlsl")]
+    pub(crate) gen_hlsl: bool,
+    #[cfg(feature = "hlsl")]
+    pub(crate) hlsl_options: naga::back::hlsl::Options,
+    #[cfg(feature = "glsl")]
+    pub(crate) gen_glsl: bool,
+    #[cfg(feature = "glsl")]
+    pub(crate) glsl_options: naga::back::glsl::Options,
+    pretty: bool,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        let mut shader_defs = HashMap::new();
+        if cfg!(debug_assertions) {
+            shader_defs.insert("__DEBUG".to_string(), ShaderDefValue::Bool(true));
+        }
+
+        Self {
+            project_root: None,
+            import_paths: Vec::new(),
+            import_roots: BTreeMap::new(),
+            entrypoints: Vec::new(),
+            entrypoint_globs: Vec::new(),
+            globs_require_entry_points: false,
+            nested_modules: false,
+            threads: None,
+            cache: true,
+            capabilities: Capabilities::all(),
+            validation_flags: ValidationFlags::all(),
+            shader_defs,
+            cargo_feature_defs: false,
+            target_defs: false,
+            imported_defines: ImportedDefines::default(),
+            gen_bytemuck: cfg!(feature = "bytemuck"),
+            gen_glam: cfg!(feature = "glam"),
+            gen_encase: cfg!(feature = "encase"),
+            gen_naga: cfg!(feature = "naga"),
+            gen_wgpu: cfg!(feature = "wgpu"),
+            #[cfg(feature = "naga")]
+            embed_naga_module: false,
+            #[cfg(feature = "naga")]
+            gen_wgpu_naga_ir: false,
+            #[cfg(feature = "spirv")]
+            gen_spirv: true,
+            #[cfg(feature = "msl")]
+            gen_msl: true,
+            #[cfg(feature = "msl")]
+            msl_options: Default::default(),
+            #[cfg(feature = "hlsl")]
+            gen_hlsl: true,
+            #[cfg(feature = "hlsl")]
+            hlsl_options: Default::default(),
+            #[cfg(feature = "glsl")]
+            gen_glsl: true,
+            #[cfg(feature = "glsl")]
+            glsl_options: Default::default(),
+            pretty: true,
+        }
+    }
+}
+
+impl Generator {
+    /// Creates a generator with the default configuration, which matches the behaviour of
+    /// [`generate_from_entrypoints`](crate::generate_from_entrypoints).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory that entrypoint paths and imports are resolved relative to.
+    /// Defaults to `CARGO_MANIFEST_DIR`.
+    pub fn project_root(mut self, project_root: impl Into<PathBuf>) -> Self {
+        self.project_root = Some(project_root.into());
+        self
+    }
+
+    /// Adds a directory, relative to the project root, to look for imports in. Imports are looked for relative to the
+    /// importing file, then the project root, then each import path in the order they were added.
+    pub fn import_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.import_paths.push(path.into());
+        self
+    }
+
+    /// Names a directory, relative to the project root, so that imports starting with `@name/` are resolved within
+    /// it, e.g. `#import @common/lighting.wgsl` with the name `common`. Replaces any directory previously given the
+    /// same name.
+    pub fn import_root(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.import_roots.insert(name.into(), path.into());
+        self
+    }
+
+    /// Adds a shader file, relative to the project root, to generate a Rust module for.
+    pub fn entrypoint(self, path: impl Into<String>) -> Self {
+        self.entrypoint_with_defs(path, Vec::<(String, ShaderDefValue)>::new())
+    }
+
+    /// Adds a shader file, relative to the project root, with shader defs that apply only to it.
+    /// These take precedence over defs given for every entrypoint.
+    pub fn entrypoint_with_defs<S: Into<String>>(
+        mut self,
+        path: impl Into<String>,
+        defs: impl IntoIterator<Item = (S, ShaderDefValue)>,
+    ) -> Self {
+        self.entrypoints.push(Entrypoint {
+            path: path.into(),
+            shader_defs: defs
+                .into_iter()
+                .map(|(name, value)| (name.into(), value))
+                .collect(),
+            permutations: Vec::new(),
+        });
+        self
+    }
+
+    /// Builds an entrypoint once for every combination of the given boolean defs, adding the entrypoint if it
+    /// has not already been added.
+    ///
+    /// Each combination is generated as a submodule named after its enabled defs in lowercase, joined by
+    /// underscores, or `base` when none are enabled; e.g. `SKINNED` and `SHADOWS` give `base`, `skinned`,
+    /// `shadows` and `skinned_shadows`. If every combination gives identical types then a single `types` module
+    /// is shared between them.
+    pub fn permutations<S: Into<String>>(
+        mut self,
+        path: impl Into<String>,
+        defs: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let path = path.into();
+        if !self.entrypoints.iter().any(|e| e.path == path) {
+            self = self.entrypoint(path.clone());
+        }
+        let entrypoint = self
+            .entrypoints
+            .iter_mut()
+            .find(|e| e.path == path)
+            .expect("entrypoint was just added");
+        entrypoint.permutations = defs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds several shader files, relative to the project root, to generate Rust modules for.
+    pub fn entrypoints<S: Into<String>>(self, paths: impl IntoIterator<Item = S>) -> Self {
+        paths
+            .into_iter()
+            .fold(self, |generator, path| generator.entrypoint(path))
+    }
+
+    /// Adds every `.wgsl` file matching a glob pattern relative to the project root, such as
+    /// `src/shaders/**/*.wgsl`, as an entrypoint. Files that have already been added are skipped.
+    ///
+    /// The directory searched is watched for changes, so that newly added shaders are picked up.
+    pub fn entrypoint_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.entrypoint_globs.push(pattern.into());
+        self
+    }
+
+    /// Toggles only adding files matched by [`Self::entrypoint_glob`] if they contain at least one `@vertex`,
+    /// `@fragment` or `@compute` entry point, leaving the rest to be used as import-only libraries.
+    /// Defaults to off.
+    pub fn globs_require_entry_points(mut self, enabled: bool) -> Self {
+        self.globs_require_entry_points = enabled;
+        self
+    }
+
+    /// Toggles placing each shader's module within nested modules mirroring its directory, relative to the deepest
+    /// directory holding every shader within the project root, e.g. `src/shaders/lighting/pbr.wgsl` and
+    /// `src/shaders/post/bloom.wgsl` become `lighting::pbr` and `post::bloom`. Files found through an import path or
+    /// import root are placed relative to the deepest directory holding every shader found within it instead.
+    ///
+    /// Otherwise every module is at the top level, named after its file. Files with the same name are then
+    /// distinguished by appending their parent directories, e.g. `pbr_lighting` and `pbr_post`. Defaults to off.
+    pub fn nested_modules(mut self, enabled: bool) -> Self {
+        self.nested_modules = enabled;
+        self
+    }
+
+    /// Sets the number of threads used to compose, validate and generate code for shaders. Defaults to the number of
+    /// jobs cargo is running with, or the available parallelism outside of a build script.
+    ///
+    /// The generated source is the same regardless of the number of threads.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Toggles reusing the code generated for each shader by the previous run, recorded within `OUT_DIR`, if none of
+    /// the files it was composed from, its defs or the options affecting generated code have changed. Has no effect
+    /// when `OUT_DIR` isn't set. Defaults to on.
+    pub fn cache(mut self, enabled: bool) -> Self {
+        self.cache = enabled;
+        self
+    }
+
+    /// Sets the capabilities used when composing and validating shaders. Defaults to all capabilities.
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Sets the validation flags used when validating composed shaders. Defaults to all flags.
+    pub fn validation_flags(mut self, validation_flags: ValidationFlags) -> Self {
+        self.validation_flags = validation_flags;
+        self
+    }
+
+    /// Defines a shader def for every entrypoint, overwriting any previous value with the same name.
+    pub fn shader_def(mut self, name: impl Into<String>, value: ShaderDefValue) -> Self {
+        self.shader_defs.insert(name.into(), value);
+        self
+    }
+
+    /// Defines several shader defs for every entrypoint.
+    pub fn shader_defs<S: Into<String>>(
+        mut self,
+        defs: impl IntoIterator<Item = (S, ShaderDefValue)>,
+    ) -> Self {
+        self.shader_defs
+            .extend(defs.into_iter().map(|(name, value)| (name.into(), value)));
+        self
+    }
+
+    /// Toggles defining `FEATURE_<NAME>` as `true` for every enabled cargo feature of the crate being built,
+    /// e.g. `FEATURE_HDR` for a feature named `hdr`. Defaults to off.
+    pub fn cargo_feature_defs(mut self, enabled: bool) -> Self {
+        self.cargo_feature_defs = enabled;
+        self
+    }
+
+    /// Toggles defining shader defs describing the target being built for: `TARGET_OS_<OS>`,
+    /// `TARGET_ARCH_<ARCH>` and `TARGET_FAMILY_<FAMILY>` as `true`, and `TARGET_POINTER_WIDTH` as an unsigned
+    /// integer. Defaults to off.
+    pub fn target_defs(mut self, enabled: bool) -> Self {
+        self.target_defs = enabled;
+        self
+    }
+
+    /// Sets how `#define` directives within imported files apply. Defaults to [`ImportedDefines::Scoped`].
+    pub fn imported_defines(mut self, imported_defines: ImportedDefines) -> Self {
+        self.imported_defines = imported_defines;
+        self
+    }
+
+    /// Toggles deriving `bytemuck` traits on exported structs. Defaults to the `bytemuck` feature.
+    pub fn bytemuck(mut self, enabled: bool) -> Self {
+        self.gen_bytemuck = enabled;
+        self
+    }
+
+    /// Toggles representing vectors and matrices with `glam` types. Defaults to the `glam` feature.
+    pub fn glam(mut self, enabled: bool) -> Self {
+        self.gen_glam = enabled;
+        self
+    }
+
+    /// Toggles deriving `encase::ShaderType` on exported structs. Defaults to the `encase` feature.
+    pub fn encase(mut self, enabled: bool) -> Self {
+        self.gen_encase = enabled;
+        self
+    }
+
+    /// Toggles generating `naga` items. Defaults to the `naga` feature.
+    pub fn naga(mut self, enabled: bool) -> Self {
+        self.gen_naga = enabled;
+        self
+    }
+
+    /// Toggles embedding the validated `naga::Module` and its `ModuleInfo`, serialized with `bincode`, in each
+    /// generated module along with functions to deserialize them. Defaults to off.
+    ///
+    /// The crate including the generated code must depend on `bincode` 1 and the same version of `naga` as this
+    /// crate, with its `deserialize` feature.
+    #[cfg(feature = "naga")]
+    pub fn embed_naga_module(mut self, enabled: bool) -> Self {
+        self.embed_naga_module = enabled;
+        self
+    }
+
+    /// Toggles generating a `shader_source` function giving the embedded module as a `wgpu::ShaderSource::Naga`,
+    /// when embedding naga modules and generating `wgpu` helpers. Defaults to off.
+    ///
+    /// `wgpu::ShaderSource::Naga` only exists with wgpu's `naga-ir` feature, which the crate including the generated
+    /// code must enable.
+    #[cfg(feature = "naga")]
+    pub fn wgpu_naga_ir(mut self, enabled: bool) -> Self {
+        self.gen_wgpu_naga_ir = enabled;
+        self
+    }
+
+    /// Toggles generating `wgpu` helpers: bind group layouts and builders in a `bind_groups` module, vertex buffer
+    /// layouts on vertex input structs, and pipeline state for each entry point in a `pipelines` module. Defaults to
+    /// the `wgpu` feature.
+    pub fn wgpu(mut self, enabled: bool) -> Self {
+        self.gen_wgpu = enabled;
+        self
+    }
+
+    /// Toggles embedding the shader translated to SPIR-V as a `SPIRV: &[u32]` constant. Defaults to on when the
+    /// `spirv` feature is enabled.
+    #[cfg(feature = "spirv")]
+    pub fn spirv(mut self, enabled: bool) -> Self {
+        self.gen_spirv = enabled;
+        self
+    }
+
+    /// Toggles embedding the shader translated to Metal Shading Language as an `MSL: &str` constant. Defaults to
+    /// on when the `msl` feature is enabled.
+    #[cfg(feature = "msl")]
+    pub fn msl(mut self, enabled: bool) -> Self {
+        self.gen_msl = enabled;
+        self
+    }
+
+    /// Sets the options given to naga's MSL backend, such as the binding map of each entry point. Defaults to naga's
+    /// defaults.
+    #[cfg(feature = "msl")]
+    pub fn msl_options(mut self, options: naga::back::msl::Options) -> Self {
+        self.msl_options = options;
+        self
+    }
+
+    /// Toggles embedding the shader translated to HLSL as an `HLSL: &str` constant. Defaults to on when the `hlsl`
+    /// feature is enabled.
+    #[cfg(feature = "hlsl")]
+    pub fn hlsl(mut self, enabled: bool) -> Self {
+        self.gen_hlsl = enabled;
+        self
+    }
+
+    /// Sets the options given to naga's HLSL backend, such as the shader model and binding map. Defaults to naga's
+    /// defaults.
+    #[cfg(feature = "hlsl")]
+    pub fn hlsl_options(mut self, options: naga::back::hlsl::Options) -> Self {
+        self.hlsl_options = options;
+        self
+    }
+
+    /// Toggles embedding each entry point translated to GLSL as a constant within a `glsl` module. Defaults to on
+    /// when the `glsl` feature is enabled.
+    #[cfg(feature = "glsl")]
+    pub fn glsl(mut self, enabled: bool) -> Self {
+        self.gen_glsl = enabled;
+        self
+    }
+
+    /// Sets the options given to naga's GLSL backend for every entry point, such as the GLSL version and binding map.
+    /// Defaults to naga's defaults, which target OpenGL ES 3.1.
+    #[cfg(feature = "glsl")]
+    pub fn glsl_options(mut self, options: naga::back::glsl::Options) -> Self {
+        self.glsl_options = options;
+        self
+    }
+
+    /// Toggles formatting the output with `prettyplease`. Has no effect without the `prettyplease` feature.
+    pub fn pretty(mut self, enabled: bool) -> Self {
+        self.pretty = enabled;
+        self
+    }
+
+    /// Runs code generation, returning the generated Rust source.
+    ///
+    /// Every entrypoint is processed even if an earlier one fails, so that all shader errors can be reported at once.
+    pub fn generate(&self) -> Result<String, GenerateErrors> {
+        let mut watched = BTreeSet::new();
+        let generated = self.generate_watching(&mut watched);
+        // Watched even if generation failed, so that fixing a shader triggers it again
+        for path in watched {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        generated
+    }
+
+    /// Runs code generation, adding every file and directory that the generated source depends on to `watched`.
+    ///
+    /// Watched paths are absolute, as cargo takes relative paths to be relative to the manifest directory rather than
+    /// the project root.
+    fn generate_watching(&self, watched: &mut BTreeSet<PathBuf>) -> Result<String, GenerateErrors> {
+        let project_root = match &self.project_root {
+            Some(project_root) => project_root.clone(),
+            None => PathBuf::from(cargo_env("CARGO_MANIFEST_DIR")?),
+        };
+        // Build scripts run in the manifest directory, which a relative project root is taken to be relative to
+        let project_root = match std::env::current_dir() {
+            Ok(current_dir) => current_dir.join(project_root),
+            Err(error) => {
+                return Err(GenerateError::Io {
+                    path: project_root,
+                    error,
+                }
+                .into())
+            }
+        };
+
+        let search_paths =
+            SearchPaths::new(project_root.clone(), &self.import_paths, &self.import_roots);
+
+        // Defs given explicitly override any derived from the environment
+        let mut shader_defs = HashMap::new();
+        if self.cargo_feature_defs {
+            shader_defs.extend(defs::cargo_feature_defs());
+        }
+        if self.target_defs {
+            shader_defs.extend(defs::target_defs());
+        }
+        shader_defs.extend(self.shader_defs.clone());
+
+        let mut errors = Vec::new();
+        let mut vertex_input_type_names: HashMap<PathBuf, HashSet<String>> = Default::default();
+
+        let mut entrypoints = self.entrypoints.clone();
+        // Globs may spell the path of an entrypoint that was given explicitly differently
+        let canonical = |path: &str| {
+            let path = project_root.join(path);
+            path.canonicalize().unwrap_or(path)
+        };
+        let mut known_files = entrypoints
+            .iter()
+            .map(|entrypoint| canonical(&entrypoint.path))
+            .collect::<HashSet<_>>();
+        for pattern in &self.entrypoint_globs {
+            // Cargo watches every file within a directory, so new shaders trigger regeneration. The project root
+            // itself isn't watched, as it contains the build output.
+            let base_dir = discovery::glob_base_dir(pattern);
+            if !base_dir.as_os_str().is_empty() {
+                watched.insert(project_root.join(base_dir));
+            }
+            match discovery::discover_entrypoints(
+                &project_root,
+                pattern,
+                self.globs_require_entry_points,
+            ) {
+                Ok(paths) => {
+                    for path in paths {
+                        if known_files.insert(canonical(&path)) {
+                            entrypoints.push(Entrypoint::new(path));
+                        }
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        // Workers are kept between stages, so that libraries composed for entrypoints needn't be parsed again
+        let threads = self
+            .threads
+            .unwrap_or_else(parallel::default_threads)
+            .max(1);
+        let mut workers = (0..threads)
+            .map(|_| Worker::new(self.capabilities, self.validation_flags))
+            .collect::<Vec<_>>();
+
+        let cache = BuildCache::new(
+            std::env::var_os("OUT_DIR")
+                .filter(|_| self.cache)
+                .map(|out_dir| PathBuf::from(out_dir).join(CACHE_DIR_NAME)),
+        );
+
+        // Results are given in entrypoint order, so errors and the generated source don't depend on scheduling
+        let results = parallel::map_ordered(&entrypoints, &mut workers, |worker, entrypoint| {
+            let path = &entrypoint.path;
+            let Some(module_name) = Path::new(path).file_stem() else {
+                return Err(vec![GenerateError::ImportResolution {
+                    shader: path.clone(),
+                    message: format!("entrypoint `{}` does not name a `.wgsl` file", path),
+                }]);
+            };
+            let module_name = module_name.to_string_lossy().into_owned();
+
+            worker.watched.push(project_root.join(path));
+            let variants = entrypoint
+                .variants()
+                .map_err(|e| vec![e])?
+                .into_iter()
+                .map(|(variant, variant_defs)| {
+                    let mut shader_defs = shader_defs.clone();
+                    shader_defs.extend(entrypoint.shader_defs.clone());
+                    shader_defs.extend(variant_defs);
+                    (variant, shader_defs)
+                })
+                .collect();
+
+            self.load_or_compose(
+                &search_paths,
+                &cache,
+                path.clone(),
+                module_name,
+                variants,
+                worker,
+            )
+        });
+
+        let mut shaders = Vec::new();
+        for result in results {
+            match result {
+                Ok(shader) => shaders.push(shader),
+                Err(shader_errors) => errors.extend(shader_errors),
+            }
+        }
+        for shader in &shaders {
+            // record the type names under the file declaring them
+            for (file, type_name) in &shader.record.vertex_inputs {
+                vertex_input_type_names
+                    .entry(file.clone())
+                    .or_default()
+                    .insert(type_name.clone());
+            }
+        }
+
+        // Imported files that export structs need their own module for entrypoints' types to refer to. Entrypoints
+        // record every file they import indirectly too, so every such file is known at this point.
+        let entrypoint_files = shaders
+            .iter()
+            .map(|shader| shader.record.root_path().to_path_buf())
+            .collect::<HashSet<_>>();
+        let mut libraries = BTreeMap::<PathBuf, Vec<Defs>>::new();
+        for shader in &shaders {
+            for (path, builds) in &shader.record.imports {
+                if entrypoint_files.contains(path) {
+                    continue;
+                }
+                let library = libraries.entry(path.clone()).or_default();
+                for defs in builds {
+                    if !library.contains(defs) {
+                        library.push(defs.clone());
+                    }
+                }
+            }
+        }
+        let libraries = libraries.into_iter().collect::<Vec<_>>();
+        // Libraries are composed with the defs of every build importing them, so that they match what was imported
+        let results = parallel::map_ordered(&libraries, &mut workers, |worker, (path, builds)| {
+            let source = std::fs::read_to_string(path).map_err(|error| {
+                vec![GenerateError::Io {
+                    path: path.clone(),
+                    error,
+                }]
+            })?;
+            // Malformed exports are reported when the library is composed
+            let exports_structs = exports::parse_exports(&source).map_or(true, |exports| {
+                exports
+                    .iter()
+                    .any(|export| matches!(export, Export::Struct { .. }))
+            });
+            if !exports_structs {
+                return Ok(None);
+            }
+
+            let variants = builds
+                .iter()
+                .map(|defs| (None, defs.iter().cloned().collect()))
+                .collect();
+            self.load_or_compose(
+                &search_paths,
+                &cache,
+                path.to_string_lossy().into_owned(),
+                layout::file_stem(path),
+                variants,
+                worker,
+            )
+            .map(Some)
+        });
+        for result in results {
+            match result {
+                Ok(Some(shader)) => shaders.push(shader),
+                Ok(None) => {}
+                Err(shader_errors) => errors.extend(shader_errors),
+            }
+        }
+        // Every file is known once shaders are composed
+        for worker in &mut workers {
+            watched.extend(worker.watched.drain(..));
+        }
+
+        // Every module's location is needed before generating any, so that they can refer to each other
+        let files = shaders
+            .iter()
+            .map(|shader| (shader.path.clone(), shader.record.root_path().to_path_buf()))
+            .collect::<Vec<_>>();
+        let mut layout = match ModuleLayout::new(&files, self.nested_modules, &search_paths) {
+            Ok(layout) => layout,
+            Err(e) => {
+                errors.push(e);
+                return Err(GenerateErrors::new(errors));
+            }
+        };
+        for ((_, file), shader) in files.iter().zip(&shaders) {
+            for name in &shader.record.exported_structs {
+                layout.export_struct(file, name);
+            }
+            for (original, name) in &shader.record.renames {
+                layout.rename_struct(file, original, name);
+            }
+        }
+
+        // Previously generated items can be reused if nothing they refer to outside of their shader has changed
+        let layout_hash = layout.fingerprint();
+        let modules = shaders
+            .into_iter()
+            .map(|shader| {
+                let vertex_inputs = vertex_input_type_names.remove(shader.record.root_path());
+                let mut sorted_inputs = vertex_inputs.iter().flatten().collect::<Vec<_>>();
+                sorted_inputs.sort();
+                let context = cache::hash(&(layout_hash, sorted_inputs));
+                (shader, vertex_inputs, context)
+            })
+            .collect::<Vec<_>>();
+        // Syntax trees can't be sent between threads, so each module's items are passed back as source
+        let results = parallel::map_ordered(
+            &modules,
+            &mut workers,
+            |worker, (shader, vertex_inputs, context)| {
+                let module_path = layout
+                    .module_path(shader.record.root_path())
+                    .expect("every file was given a module");
+                for name in module_path {
+                    module_ident(&shader.path, name).map_err(|e| vec![e])?;
+                }
+                if shader.builds.is_none() && shader.record.context == *context {
+                    return Ok::<_, Vec<GenerateError>>((
+                        module_path.to_vec(),
+                        shader.record.tokens.clone(),
+                    ));
+                }
+
+                // Items generated by a previous run that refer to other modules need their shader composing again
+                let recomposed;
+                let builds = match &shader.builds {
+                    Some(builds) => builds,
+                    None => {
+                        recomposed = compose_variants(
+                            &search_paths,
+                            &shader.path,
+                            &shader.variants,
+                            self.imported_defines,
+                            worker,
+                        )?;
+                        &recomposed
+                    }
+                };
+                let items = self
+                    .entrypoint_items(
+                        &shader.path,
+                        shader.module_name.clone(),
+                        builds,
+                        vertex_inputs.clone(),
+                        module_path.len() - 1,
+                        &layout,
+                    )
+                    .map_err(|e| vec![e])?;
+                let tokens = quote::quote! { #(#items)* }.to_string();
+                cache.store(
+                    &shader.path,
+                    &Record {
+                        context: *context,
+                        tokens: tokens.clone(),
+                        ..shader.record.clone()
+                    },
+                );
+
+                Ok((module_path.to_vec(), tokens))
+            },
+        );
+        let modules = results
+            .into_iter()
+            .filter_map(|result| result.map_err(|e| errors.extend(e)).ok())
+            .map(|(module_path, items)| {
+                let module_path = module_path
+                    .iter()
+                    .map(|name| {
+                        syn::parse_str::<syn::Ident>(name).expect("module names were checked")
+                    })
+                    .collect();
+                let items = syn::parse_str::<syn::File>(&items)
+                    .expect("generated items are valid Rust")
+                    .items;
+                (module_path, items)
+            })
+            .collect();
+        let items = layout::nest_modules(modules);
+
+        if !errors.is_empty() {
+            return Err(GenerateErrors::new(errors));
+        }
+
+        Ok(self.unparse(items))
+    }
+
+    /// Runs code generation, writing the generated Rust source to the given file.
+    ///
+    /// The file is left untouched if it already holds the generated source, so that cargo doesn't consider the
+    /// crate including it to have changed.
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<(), GenerateErrors> {
+        let path = path.as_ref();
+        let generated = self.generate()?;
+        if std::fs::read_to_string(path).is_ok_and(|existing| existing == generated) {
+            return Ok(());
+        }
+        std::fs::write(path, generated).map_err(|error| GenerateError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Ok(())
+    }
+
+    /// Runs code generation, writing the generated Rust source to a file in `OUT_DIR` that
+    /// [`include_wgsl_oil_generated!`](crate::include_wgsl_oil_generated) includes. Returns the path written to.
+    pub fn write_to_out_dir(&self) -> Result<PathBuf, GenerateErrors> {
+        let out_dir = PathBuf::from(cargo_env("OUT_DIR")?);
+        let path = out_dir.join(crate::OUT_DIR_FILE_NAME);
+        self.write_to(&path)?;
+        Ok(path)
+    }
+
+    /// Gives a shader as recorded in the cache if none of its files have changed, otherwise composing it.
+    fn load_or_compose(
+        &self,
+        search_paths: &SearchPaths,
+        cache: &BuildCache,
+        path: String,
+        module_name: String,
+        variants: Vec<Variant>,
+        worker: &mut Worker,
+    ) -> Result<Shader, Vec<GenerateError>> {
+        let key = self.cache_key(search_paths, &path, &variants);
+        if let Some(record) = cache.load(&path, key) {
+            // Entrypoints are watched before they are loaded, and libraries are among their entrypoints' imports
+            worker
+                .watched
+                .extend(record.files[1..].iter().map(|(file, _)| file.clone()));
+            return Ok(Shader {
+                path,
+                module_name,
+                variants,
+                record,
+                builds: None,
+            });
+        }
+
+        let builds = compose_variants(
+            search_paths,
+            &path,
+            &variants,
+            self.imported_defines,
+            worker,
+        )?;
+        let record = new_record(key, &builds).map_err(|e| vec![e])?;
+        Ok(Shader {
+            path,
+            module_name,
+            variants,
+            record,
+            builds: Some(builds),
+        })
+    }
+
+    /// Hashes everything that a shader's generated items depend on other than its files and the other modules.
+    fn cache_key(&self, search_paths: &SearchPaths, path: &str, variants: &[Variant]) -> u64 {
+        let variants = variants
+            .iter()
+            .map(|(variant, shader_defs)| {
+                let mut shader_defs = shader_defs
+                    .iter()
+                    .map(|(name, value)| format!("{}={:?}", name, value))
+                    .collect::<Vec<_>>();
+                shader_defs.sort();
+                (variant, shader_defs)
+            })
+            .collect::<Vec<_>>();
+
+        let options = format!(
+            "{:?} {:?} {:?} {:?} {:?} bytemuck={} glam={} encase={} naga={} wgpu={}",
+            self.capabilities,
+            self.validation_flags,
+            self.imported_defines,
+            self.import_paths,
+            self.import_roots,
+            self.gen_bytemuck,
+            self.gen_glam,
+            self.gen_encase,
+            self.gen_naga,
+            self.gen_wgpu,
+        );
+        #[cfg(feature = "naga")]
+        let options = format!(
+            "{} embed_naga_module={} wgpu_naga_ir={}",
+            options, self.embed_naga_module, self.gen_wgpu_naga_ir
+        );
+        #[cfg(feature = "spirv")]
+        let options = format!("{} spirv={}", options, self.gen_spirv);
+        #[cfg(feature = "msl")]
+        let options = format!("{} msl={} {:?}", options, self.gen_msl, self.msl_options);
+        #[cfg(feature = "hlsl")]
+        let options = format!("{} hlsl={} {:?}", options, self.gen_hlsl, self.hlsl_options);
+        #[cfg(feature = "glsl")]
+        let options = format!("{} glsl={} {:?}", options, self.gen_glsl, self.glsl_options);
+
+        // Shaders are named relative to the project root, so the same name may be another file under another root
+        cache::hash(&(search_paths.project_root(), path, variants, options))
+    }
+
+    /// Generates the items within the module for an entrypoint, from each of its builds.
+    fn entrypoint_items(
+        &self,
+        path: &str,
+        module_name: String,
+        builds: &[Composed],
+        vertex_inputs: Option<HashSet<String>>,
+        depth: usize,
+        layout: &ModuleLayout,
+    ) -> Result<Vec<syn::Item>, GenerateError> {
+        if let [build @ Composed { variant: None, .. }] = builds {
+            return module_items(self, build, module_name, vertex_inputs, depth, layout);
+        }
+        if builds.iter().all(|build| build.variant.is_none()) {
+            return self.library_items(path, module_name, builds, vertex_inputs, depth, layout);
+        }
+
+        let mut variants = builds
+            .iter()
+            .map(|build| {
+                let variant = build.variant.as_deref().expect("permutations are named");
+                let name = module_ident(path, &layout::sanitize_ident(variant))?;
+                let items = module_items(
+                    self,
+                    build,
+                    module_name.clone(),
+                    vertex_inputs.clone(),
+                    depth + 1,
+                    layout,
+                )?;
+                Ok((name, items))
+            })
+            .collect::<Result<Vec<_>, GenerateError>>()?;
+
+        let mut items = Vec::new();
+
+        // Share the types between permutations if they are all the same
+        let types_modules = variants
+            .iter()
+            .map(|(_, items)| types_module(items).map(|m| m.to_token_stream().to_string()))
+            .collect::<Vec<_>>();
+        let shareable = types_modules.iter().all(|m| m.is_some())
+            && types_modules.windows(2).all(|pair| pair[0] == pair[1]);
+        if shareable {
+            // Generated again at the top level so that paths to other modules resolve
+            let shared = module_items(self, &builds[0], module_name, vertex_inputs, depth, layout)?;
+            items.extend(types_module(&shared).cloned().map(syn::Item::Mod));
+
+            for (_, variant_items) in &mut variants {
+                variant_items.retain(|item| !is_types_module(item));
+                variant_items.push(parse_quote! { pub use super::types; });
+            }
+        }
+
+        items.extend(variants.into_iter().map(|(name, variant_items)| {
+            parse_quote! {
+                pub mod #name {
+                    #(#variant_items)*
+                }
+            }
+        }));
+
+        Ok(items)
+    }
+
+    /// Generates the items for an imported file built with the defs of each shader importing it. Entrypoints'
+    /// types all refer to the one module, so every build must agree on its exported structs.
+    fn library_items(
+        &self,
+        path: &str,
+        module_name: String,
+        builds: &[Composed],
+        vertex_inputs: Option<HashSet<String>>,
+        depth: usize,
+        layout: &ModuleLayout,
+    ) -> Result<Vec<syn::Item>, GenerateError> {
+        let mut first: Option<(Vec<syn::Item>, Option<String>)> = None;
+        for build in builds {
+            let items = module_items(
+                self,
+                build,
+                module_name.clone(),
+                vertex_inputs.clone(),
+                depth,
+                layout,
+            )?;
+            let types = types_module(&items).map(|m| m.to_token_stream().to_string());
+            match &first {
+                None => first = Some((items, types)),
+                Some((_, first_types)) if *first_types == types => {}
+                Some(_) => {
+                    return Err(GenerateError::Codegen {
+                        shader: path.to_owned(),
+                        message: "the file is imported with shader defs that give its exported structs different \
+                            definitions, so no single module can be generated for them"
+                            .to_owned(),
+                    })
+                }
+            }
+        }
+        Ok(first.map(|(items, _)| items).unwrap_or_default())
+    }
+
+    fn unparse(&self, items: Vec<syn::Item>) -> String {
+        #[cfg(feature = "prettyplease")]
+        if self.pretty {
+            return prettyplease::unparse(&syn::File {
+                items,
+                shebang: None,
+                attrs: vec![],
+            });
+        }
+
+        let result = quote::quote! {
+            #(#items)*
+        };
+        result.to_string()
+    }
+}
+
+/// Composes and validates every variant of a shader with its defs, giving the builds only if every variant succeeded.
+fn compose_variants(
+    search_paths: &SearchPaths,
+    path: &str,
+    variants: &[Variant],
+    imported_defines: ImportedDefines,
+    worker: &mut Worker,
+) -> Result<Vec<Composed>, Vec<GenerateError>> {
+    let (composer, validator) = worker.get();
+    let mut errors = Vec::new();
+    let mut builds = Vec::new();
+    let mut dependents = Vec::new();
+    for (variant, shader_defs) in variants {
+        match compose_entrypoint(
+            search_paths,
+            path,
+            shader_defs.clone(),
+            imported_defines,
+            composer,
+            validator,
+            &mut dependents,
+        ) {
+            Ok(build) => builds.push(Composed {
+                variant: variant.clone(),
+                ..build
+            }),
+            Err(e) => errors.push(e),
+        }
+    }
+    worker.watched.extend(dependents);
+
+    if errors.is_empty() {
+        Ok(builds)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Describes the builds of a shader, so that its module can be generated again without composing it.
+fn new_record(key: u64, builds: &[Composed]) -> Result<Record, GenerateError> {
+    let sourcecode = &builds[0].sourcecode;
+
+    // Each import is recorded with the defs of every build importing it, for its own module to be composed with
+    let mut imports = Vec::<(PathBuf, Vec<Defs>)>::new();
+    for build in builds {
+        let mut defs = build
+            .sourcecode
+            .defs()
+            .iter()
+            .map(|(name, value)| (name.clone(), *value))
+            .collect::<Defs>();
+        defs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (_, path) in build.sourcecode.imported_modules() {
+            let path = path.to_path_buf();
+            let index = match imports.iter().position(|(imported, _)| *imported == path) {
+                Some(index) => index,
+                None => {
+                    imports.push((path, Vec::new()));
+                    imports.len() - 1
+                }
+            };
+            if !imports[index].1.contains(&defs) {
+                imports[index].1.push(defs.clone());
+            }
+        }
+    }
+
+    let mut missed_imports = Vec::new();
+    for build in builds {
+        for path in build.sourcecode.missed_imports() {
+            if !missed_imports.contains(path) {
+                missed_imports.push(path.clone());
+            }
+        }
+    }
+
+    let files = std::iter::once(sourcecode.root_path())
+        .chain(imports.iter().map(|(path, _)| path.clone()))
+        .map(|path| match cache::hash_file(&path) {
+            Ok(hash) => Ok((path, hash)),
+            Err(error) => Err(GenerateError::Io { path, error }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut vertex_inputs = Vec::new();
+    for build in builds {
+        let vertex_entries = build
+            .module
+            .entry_points
+            .iter()
+            .filter(|e| e.stage == naga::ShaderStage::Vertex);
+        // find the (possibly imported) types used as vertex inputs, and the files declaring them
+        for (module, type_name) in vertex_entries.flat_map(|e| vertex_input_types(e, &build.module))
+        {
+            let file = match module {
+                Some(module) => build
+                    .sourcecode
+                    .imported_modules()
+                    .iter()
+                    .find(|(name, _)| *name == module)
+                    .map(|(_, path)| path.to_path_buf()),
+                None => Some(build.sourcecode.root_path()),
+            };
+            if let Some(file) = file {
+                vertex_inputs.push((file, type_name));
+            }
+        }
+    }
+
+    let mut exported_structs = sourcecode
+        .exports()
+        .iter()
+        .filter_map(|export| match export {
+            Export::Struct { struct_name, .. } => Some(struct_name.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    exported_structs.sort();
+    let mut renames = exports::renamed_structs(sourcecode.exports())
+        .map(|(original, name)| (original.to_owned(), name.to_owned()))
+        .collect::<Vec<_>>();
+    renames.sort();
+
+    Ok(Record {
+        key,
+        files,
+        missed_imports,
+        imports,
+        vertex_inputs,
+        exported_structs,
+        renames,
+        ..Default::default()
+    })
+}
+
+/// Resolves, composes and validates a single entrypoint, giving an unnamed build. The files it imports are added to
+/// `dependents`.
+fn compose_entrypoint(
+    search_paths: &SearchPaths,
+    path: &str,
+    shader_defs: HashMap<String, ShaderDefValue>,
+    imported_defines: ImportedDefines,
+    composer: &mut CachingComposer,
+    validator: &mut naga::valid::Validator,
+    dependents: &mut Vec<PathBuf>,
+) -> Result<Composed, GenerateError> {
+    let mut sourcecode = Sourcecode::new(search_paths.clone(), path)?;
+
+    let composed = sourcecode.compose(composer, shader_defs, imported_defines);
+    // Dependents are discovered while composing, and are needed even if composition failed
+    dependents.extend(sourcecode.dependents());
+    let module = composed?;
+
+    let info = validator
+        .validate(&module)
+        .map_err(|e| sourcecode.validation_error(&e, composer))?;
+
+    Ok(Composed {
+        variant: None,
+        sourcecode,
+        module,
+        info,
+    })
+}
+
+/// Reads an environment variable that cargo sets for build scripts.
+fn cargo_env(variable: &str) -> Result<String, GenerateError> {
+    std::env::var(variable).map_err(|error| GenerateError::Env {
+        variable: variable.to_owned(),
+        error,
+    })
+}
+
+/// Parses a name to be used for a generated module.
+fn module_ident(shader: &str, name: &str) -> Result<syn::Ident, GenerateError> {
+    syn::parse_str::<syn::Ident>(name).map_err(|e| GenerateError::Codegen {
+        shader: shader.to_owned(),
+        message: format!("`{}` is not a valid Rust module name: {}", name, e),
+    })
+}
+
+fn is_types_module(item: &syn::Item) -> bool {
+    matches!(item, syn::Item::Mod(m) if m.ident == TYPES_MODULE_NAME)
+}
+
+/// Finds the module of exported types within generated items.
+fn types_module(items: &[syn::Item]) -> Option<&syn::ItemMod> {
+    items.iter().find_map(|item| match item {
+        syn::Item::Mod(m) if m.ident == TYPES_MODULE_NAME => Some(m),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant_names(permutations: &[&str]) -> Result<Vec<String>, GenerateError> {
+        let mut entrypoint = Entrypoint::new("shader.wgsl".to_owned());
+        entrypoint.permutations = permutations.iter().map(|def| def.to_string()).collect();
+        Ok(entrypoint
+            .variants()?
+            .into_iter()
+            .map(|(name, _)| name.unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn variants_are_named_by_enabled_defs() {
+        assert_eq!(
+            variant_names(&["SKINNED", "SHADOWS"]).unwrap(),
+            ["base", "skinned", "shadows", "skinned_shadows"]
+        );
+    }
+
+    #[test]
+    fn clashing_variant_names_are_rejected() {
+        assert!(variant_names(&["A", "B", "A_B"]).is_err());
+        assert!(variant_names(&["BASE"]).is_err());
+        assert!(variant_names(&["A", "a"]).is_err());
+        assert!(variant_names(&["TYPES"]).is_err());
+    }
+
+    #[test]
+    fn permutation_count_is_limited() {
+        let defs = (0..MAX_PERMUTATION_DEFS)
+            .map(|i| format!("DEF_{}", i))
+            .collect::<Vec<_>>();
+        let defs = defs.iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            variant_names(&defs).unwrap().len(),
+            1 << MAX_PERMUTATION_DEFS
+        );
+
+        let defs = (0..64).map(|i| format!("DEF_{}", i)).collect::<Vec<_>>();
+        let defs = defs.iter().map(String::as_str).collect::<Vec<_>>();
+        assert!(variant_names(&defs).is_err());
+    }
+
+    // The cache is kept in `OUT_DIR`, which no other unit test uses
+    #[test]
+    fn watched_paths_are_absolute_with_any_project_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap().join("assets");
+        for (path, source) in [
+            ("main.wgsl", "#import lib.wgsl\n\n@compute @workgroup_size(1)\nfn main() { let value = lib::one(); }\n"),
+            ("lib.wgsl", "fn one() -> f32 { return 1.0; }\n"),
+            ("more/other.wgsl", "@compute @workgroup_size(1)\nfn main() {}\n"),
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        std::env::set_var("OUT_DIR", dir.path().join("out"));
+
+        let generator = Generator::new()
+            .project_root(&root)
+            .entrypoint("main.wgsl")
+            .entrypoint_glob("more/*.wgsl");
+        let expected = ["lib.wgsl", "main.wgsl", "more", "more/other.wgsl"]
+            .map(|path| root.join(path))
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        // The second run loads the shaders from the cache
+        for _ in 0..2 {
+            let mut watched = BTreeSet::new();
+            generator.generate_watching(&mut watched).unwrap();
+            assert_eq!(watched, expected);
+        }
+    }
+
+    #[test]
+    fn globs_skip_entrypoints_given_with_other_spellings() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("main.wgsl"),
+            "@compute @workgroup_size(1)\nfn main() {}\n",
+        )
+        .unwrap();
+
+        let generated = Generator::new()
+            .project_root(dir.path())
+            .entrypoint("./main.wgsl")
+            .entrypoint_glob("*.wgsl")
+            .cache(false)
+            .generate()
+            .unwrap();
+        assert_eq!(
+            generated
+                .lines()
+                .filter(|line| line.starts_with("pub mod main "))
+                .count(),
+            1,
+            "{}",
+            generated
+        );
+    }
+}
@@ -1,21 +1,21 @@
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     fmt::Display,
+    ops::Range,
     path::PathBuf,
 };
 
 use daggy::{petgraph::visit::IntoNodeReferences, Walker};
-use regex::{Captures, Regex};
 
-use crate::module::Module;
+use naga_oil::compose::ShaderDefValue;
 
-lazy_static::lazy_static! {
-    static ref IMPORT_CUSTOM_PATH_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)").unwrap();
-    static ref IMPORT_CUSTOM_PATH_AS_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s+as\s+([^\s]+)").unwrap();
-    static ref IMPORT_ITEMS_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s+([^\s]+(?:\s*,\s*[^\s]+)*)").unwrap();
-    static ref IMPORT_SINGLE_ITEM_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s*::\s*([^\s{]+)").unwrap();
-    static ref IMPORT_ITEMS_BRACKETS_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s*::\s*\{\s*([^\s]+(?:\s*,\s*[^\s]+)*)\s*\}").unwrap();
-}
+use crate::{
+    error::GenerateError,
+    generator::ImportedDefines,
+    lexer,
+    module::{Module, SearchPaths},
+    preprocess,
+};
 
 /// Finds an arbitrary path between two nodes in a dag.
 fn find_any_path<N, E>(
@@ -28,54 +28,117 @@ fn find_any_path<N, E>(
         .expect("`find_any_path` should only be called when such a path exists")
 }
 
-/// Finds all import declarations in a source file, returning all of the paths given.
-fn all_imports_in_source(source: &str) -> HashSet<&str> {
-    let mut requirements = HashSet::new();
-    for import in IMPORT_CUSTOM_PATH_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
-    }
-    for import in IMPORT_CUSTOM_PATH_AS_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
-    }
-    for import in IMPORT_ITEMS_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
-    }
-    for import in IMPORT_SINGLE_ITEM_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
-    }
-    for import in IMPORT_ITEMS_BRACKETS_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
+/// An `#import` of a WGSL file, as opposed to a module known to naga_oil by name.
+struct Import<'a> {
+    /// The path given, such as `../lighting.wgsl`.
+    path: &'a str,
+    /// The byte range of the path within the source.
+    span: Range<usize>,
+}
+
+/// The `#import`s of WGSL files in a source file that naga_oil would process with a set of shader defs.
+struct SourceImports<'a> {
+    imports: Vec<Import<'a>>,
+    /// The ranges of the source to blank out before giving it to naga_oil, if any imports are within conditional
+    /// blocks that aren't taken. naga_oil requires every module referred to within a file to have been added, even
+    /// from blocks that aren't taken, so those blocks are removed entirely.
+    skipped: Vec<Range<usize>>,
+}
+
+/// Finds every `#import` of a WGSL file in a source file, ignoring any within comments or within `#ifdef`/`#if`
+/// blocks that aren't taken with the given shader defs.
+///
+/// Any import form accepted by naga_oil may follow the path, e.g. `#import a.wgsl as A`, `#import a.wgsl::b` or
+/// `#import a.wgsl::{b, c}`.
+fn imports_in_source<'a>(
+    source: &'a str,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> SourceImports<'a> {
+    let directives = lexer::directives(source);
+    // Conditionals that can't be evaluated are left for naga_oil to report
+    let inactive =
+        preprocess::inactive_ranges(source.len(), &directives, shader_defs).unwrap_or_default();
+    let is_inactive = |span: &Range<usize>| {
+        inactive
+            .iter()
+            .any(|range| range.start <= span.start && span.end <= range.end)
+    };
+
+    let (imports, skipped_imports): (Vec<_>, Vec<_>) = directives
+        .iter()
+        .filter(|directive| directive.name == "import")
+        .filter_map(|directive| {
+            let path = wgsl_path(directive.args)?;
+            let start = directive.args_span.start;
+            Some(Import {
+                path,
+                span: start..start + path.len(),
+            })
+        })
+        .partition(|import| !is_inactive(&import.span));
+
+    // Other directives, such as `#define`, apply regardless of conditionals so are kept
+    let mut skipped = Vec::new();
+    if !skipped_imports.is_empty() {
+        for range in &inactive {
+            let mut start = range.start;
+            for directive in &directives {
+                if directive.name != "import"
+                    && range.start <= directive.span.start
+                    && directive.span.end <= range.end
+                {
+                    skipped.push(start..directive.span.start);
+                    start = directive.span.end;
+                }
+            }
+            skipped.push(start..range.end);
+        }
     }
-    requirements
+
+    SourceImports { imports, skipped }
+}
+
+/// Gives the path at the start of an import's arguments, if it is a path to a WGSL file.
+fn wgsl_path(args: &str) -> Option<&str> {
+    let token_len = args
+        .find(|c: char| c.is_whitespace() || c == ',' || c == '{')
+        .unwrap_or(args.len());
+    let token = &args[..token_len];
+
+    // The path is followed by either the end of the token or the `::` of an item import
+    token.match_indices(".wgsl").find_map(|(i, extension)| {
+        let end = i + extension.len();
+        (end == token.len() || token[end..].starts_with("::")).then(|| &token[..end])
+    })
 }
 
 pub(crate) fn replace_imports_in_source(
     source: &str,
     importing: &Module,
-    project_root: &PathBuf,
+    search_paths: &SearchPaths,
     module_names: &HashMap<Module, String>,
+    shader_defs: &HashMap<String, ShaderDefValue>,
 ) -> String {
-    IMPORT_CUSTOM_PATH_REGEX
-        .replace_all(source, |capture: &Captures<'_>| {
-            let full = capture.get(0).unwrap().as_str();
-
-            let name = capture.get(1).unwrap().as_str();
-            let sub = match Module::resolve_module(importing, &project_root, name)
-                .ok()
-                .and_then(|import| module_names.get(&import).cloned())
-            {
-                Some(sub) => sub,
-                None => return full.to_owned(),
-            };
+    let SourceImports { imports, skipped } = imports_in_source(source, shader_defs);
+    let mut replaced = preprocess::blank_ranges(source, &skipped);
+    // Replaced from the end, so that the spans of earlier imports are unaffected by any change in length
+    for import in imports.into_iter().rev() {
+        let sub = match Module::resolve_module(importing, search_paths, import.path)
+            .ok()
+            .and_then(|import| module_names.get(&import).cloned())
+        {
+            Some(sub) => sub,
+            None => continue,
+        };
 
-            // Right alignment is needed for naga_oil to correctly parse rust-style imports:
-            // `#import foo.wgsl::bar` will become `#import      foo::bar`
-            // naga_oil does not support spaces between import items
-            let sub = format!("{:>len$}", sub, len = name.len());
+        // Right alignment is needed for naga_oil to correctly parse rust-style imports:
+        // `#import foo.wgsl::bar` will become `#import      foo::bar`
+        // naga_oil does not support spaces between import items
+        let sub = format!("{:>len$}", sub, len = import.path.len());
 
-            capture.get(0).unwrap().as_str().replace(name, &sub)
-        })
-        .to_string()
+        replaced.replace_range(import.span, &sub);
+    }
+    replaced
 }
 
 pub(crate) enum ImportResolutionError {
@@ -85,10 +148,66 @@ pub(crate) enum ImportResolutionError {
     Unresolved {
         requested: String,
         importer: Module,
-        searched: HashSet<PathBuf>,
+        /// The line of the importing file that the import is on, if known.
+        line: Option<usize>,
+        /// Every location looked in, in the order they were tried.
+        searched: Vec<PathBuf>,
+    },
+    UnknownRoot {
+        requested: String,
+        importer: Module,
+        /// The line of the importing file that the import is on, if known.
+        line: Option<usize>,
+        /// The name following the `@` of the import.
+        root: String,
+    },
+    NotWgsl {
+        requested: String,
+        importer: Module,
+        /// The line of the importing file that the import is on, if known.
+        line: Option<usize>,
+        /// The file that the import resolved to.
+        path: PathBuf,
+    },
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
     },
 }
 
+impl ImportResolutionError {
+    /// Records the line of the importing file that an unresolved import was requested on.
+    fn on_line(mut self, import_line: usize) -> Self {
+        if let ImportResolutionError::Unresolved { line, .. }
+        | ImportResolutionError::UnknownRoot { line, .. }
+        | ImportResolutionError::NotWgsl { line, .. } = &mut self
+        {
+            *line = Some(import_line);
+        }
+        self
+    }
+
+    /// Converts this error into the public error type, attributing it to the given entrypoint.
+    pub(crate) fn into_generate_error(self, shader: &str) -> GenerateError {
+        match self {
+            ImportResolutionError::Cycle { cycle_path } => GenerateError::Cycle {
+                shader: shader.to_owned(),
+                cycle: cycle_path
+                    .iter()
+                    .map(|module| module.path().to_path_buf())
+                    .collect(),
+            },
+            ImportResolutionError::Io { path, error } => GenerateError::Io { path, error },
+            err @ (ImportResolutionError::Unresolved { .. }
+            | ImportResolutionError::UnknownRoot { .. }
+            | ImportResolutionError::NotWgsl { .. }) => GenerateError::ImportResolution {
+                shader: shader.to_owned(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
 impl Display for ImportResolutionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -102,19 +221,66 @@ impl Display for ImportResolutionError {
             ImportResolutionError::Unresolved {
                 requested,
                 importer,
+                line,
                 searched,
             } => {
                 write!(
                     f,
-                    "could not resolve import `{}` in file `{}`:\nlooked in location(s) {}",
-                    requested,
-                    importer,
+                    "could not resolve import `{}` in file `{}`",
+                    requested, importer
+                )?;
+                if let Some(line) = line {
+                    write!(f, " on line {}", line)?;
+                }
+                write!(
+                    f,
+                    ":\nlooked in location(s) {}",
                     searched
                         .iter()
                         .map(|path| format!("`{}`", path.display()))
-                        .fold(String::new(), |a, b| a + ", " + &b)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            }
+            ImportResolutionError::UnknownRoot {
+                requested,
+                importer,
+                line,
+                root,
+            } => {
+                write!(
+                    f,
+                    "could not resolve import `{}` in file `{}`",
+                    requested, importer
+                )?;
+                if let Some(line) = line {
+                    write!(f, " on line {}", line)?;
+                }
+                write!(f, ": no import root named `{}` was given", root)
+            }
+            ImportResolutionError::NotWgsl {
+                requested,
+                importer,
+                line,
+                path,
+            } => {
+                write!(
+                    f,
+                    "could not resolve import `{}` in file `{}`",
+                    requested, importer
+                )?;
+                if let Some(line) = line {
+                    write!(f, " on line {}", line)?;
+                }
+                write!(
+                    f,
+                    ": `{}` does not have the required `.wgsl` extension",
+                    path.display()
                 )
             }
+            ImportResolutionError::Io { path, error } => {
+                write!(f, "could not read `{}`: {}", path.display(), error)
+            }
         }
     }
 }
@@ -123,16 +289,27 @@ impl Display for ImportResolutionError {
 pub(crate) struct ImportOrder {
     dag: daggy::Dag<Module, ()>,
     node_of_interest: daggy::NodeIndex,
+    /// Files that imports would have resolved to before the files they did, had they existed.
+    missed: Vec<PathBuf>,
 }
 
 impl ImportOrder {
     /// Given a root module, traverses the file system to find all imports
+    ///
+    /// Only imports that naga_oil would process with the given shader defs are followed, so files imported within
+    /// `#ifdef`/`#if` blocks that aren't taken needn't exist and can't form cycles. Scoped defines within imported
+    /// files also apply to their own imports. If `skip_unresolved` is set, imports that can't be found are left out
+    /// rather than being reported.
     pub(crate) fn calculate(
         root_module: &Module,
-        project_root: &PathBuf,
+        search_paths: &SearchPaths,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+        imported_defines: ImportedDefines,
+        skip_unresolved: bool,
     ) -> Result<Self, ImportResolutionError> {
         let mut order = daggy::Dag::<Module, ()>::new();
         let mut nodes = HashMap::new();
+        let mut missed = Vec::new();
 
         // Follow a DFS over imports, detecting cycles using daggy.
         let mut search_front = VecDeque::from(vec![(Option::<Module>::None, root_module.clone())]);
@@ -166,9 +343,50 @@ impl ImportOrder {
             }
 
             // Then add the imports requested by this file
-            let source = imported.read_to_string();
-            for requested in all_imports_in_source(&source) {
-                let import = Module::resolve_module(&imported, project_root, requested)?;
+            let source = imported
+                .read_to_string()
+                .map_err(|error| ImportResolutionError::Io {
+                    path: imported.path().to_path_buf(),
+                    error,
+                })?;
+            // Scoped defines decide which of the imports of the file declaring them are used
+            let scoped_defs;
+            let file_defs =
+                if imported_defines == ImportedDefines::Scoped && imported != *root_module {
+                    scoped_defs = shader_defs
+                        .clone()
+                        .into_iter()
+                        .chain(preprocess::defines(&lexer::directives(&source)))
+                        .collect();
+                    &scoped_defs
+                } else {
+                    shader_defs
+                };
+
+            let mut requested_paths = HashSet::new();
+            for requested in imports_in_source(&source, file_defs).imports {
+                // A file may import several items from the same file separately
+                if !requested_paths.insert(requested.path) {
+                    continue;
+                }
+                let line = source[..requested.span.start].matches('\n').count() + 1;
+                let (import, misses) = match Module::resolve_module_with_misses(
+                    &imported,
+                    search_paths,
+                    requested.path,
+                ) {
+                    Ok(resolved) => resolved,
+                    Err(
+                        ImportResolutionError::Unresolved { .. }
+                        | ImportResolutionError::UnknownRoot { .. },
+                    ) if skip_unresolved => continue,
+                    Err(e) => return Err(e.on_line(line)),
+                };
+                for miss in misses {
+                    if !missed.contains(&miss) {
+                        missed.push(miss);
+                    }
+                }
                 search_front.push_back((Some(imported.clone()), import));
             }
         }
@@ -176,9 +394,15 @@ impl ImportOrder {
         Ok(ImportOrder {
             dag: order,
             node_of_interest: nodes[&root_module],
+            missed,
         })
     }
 
+    /// Gives every file that an import would have resolved to instead of the file it did, had it existed.
+    pub(crate) fn missed(&self) -> &[PathBuf] {
+        &self.missed
+    }
+
     /// Gives a vector of every node that needs to be imported, in order of import from leaf to the node of interest.
     /// The root node is excluded from the import order.
     pub(crate) fn modules(mut self) -> Vec<Module> {
@@ -256,3 +480,61 @@ impl ImportOrder {
         forwards
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wgsl_paths() {
+        assert_eq!(wgsl_path("a.wgsl"), Some("a.wgsl"));
+        assert_eq!(
+            wgsl_path("../lights/a.wgsl as lights"),
+            Some("../lights/a.wgsl")
+        );
+        assert_eq!(wgsl_path("a.wgsl::item"), Some("a.wgsl"));
+        assert_eq!(wgsl_path("a.wgsl::{b, c}"), Some("a.wgsl"));
+        assert_eq!(wgsl_path("a.wgsl::{\r\n  b,\r\n}"), Some("a.wgsl"));
+        assert_eq!(wgsl_path("a.wgsl\t"), Some("a.wgsl"));
+        assert_eq!(wgsl_path("@lib/a.wgsl::b"), Some("@lib/a.wgsl"));
+        // Paths may contain `.wgsl` before the extension
+        assert_eq!(wgsl_path("a.wgsl.wgsl"), Some("a.wgsl.wgsl"));
+        // Imports of modules known to naga_oil by name are left alone
+        assert_eq!(wgsl_path("bevy_pbr::mesh"), None);
+        assert_eq!(wgsl_path("a.wgslx"), None);
+        assert_eq!(wgsl_path(""), None);
+    }
+
+    fn import_paths(source: &str) -> Vec<&str> {
+        imports_in_source(source, &HashMap::new())
+            .imports
+            .into_iter()
+            .map(|import| {
+                assert_eq!(&source[import.span.clone()], import.path);
+                import.path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imports_in_source_with_every_form() {
+        let source = "\
+#import a.wgsl as A\r
+#import b.wgsl::item\r
+#import c.wgsl::{\r
+    // d.wgsl\r
+    first, /* e.wgsl */ second,\r
+}\r
+// #import f.wgsl\r
+#import bevy_pbr::mesh\r
+#ifdef MISSING\r
+#import g.wgsl\r
+#endif\r
+#import h.wgsl::{x, y}\r
+";
+        assert_eq!(
+            import_paths(source),
+            ["a.wgsl", "b.wgsl", "c.wgsl", "h.wgsl"]
+        );
+    }
+}
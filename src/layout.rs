@@ -0,0 +1,433 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{error::GenerateError, module::SearchPaths};
+
+/// Where each generated module sits within the generated file, along with anything that the other generated
+/// modules need to know about it to refer to its items.
+#[derive(Default)]
+pub(crate) struct ModuleLayout {
+    /// The names of the modules leading from the top level of the generated file to each shader's module,
+    /// by the absolute path of the shader.
+    paths: HashMap<PathBuf, Vec<String>>,
+    /// The absolute shader path and shader struct name of every exported struct.
+    exported_structs: HashSet<(PathBuf, String)>,
+    /// The Rust names of exported structs that have been renamed, by absolute shader path and shader struct name.
+    renamed_structs: HashMap<(PathBuf, String), String>,
+}
+
+impl ModuleLayout {
+    /// Assigns a module to each of the given absolute shader paths.
+    ///
+    /// If `nested` is set then each shader is placed in nested modules mirroring its directory, relative to the
+    /// deepest directory holding every shader found within the same directory as it: the project root, or an import
+    /// root or import path. Otherwise every module is at the top level and named after its file, with colliding names
+    /// extended by parent directory names until they are distinguished.
+    ///
+    /// Shaders are given by their name, relative to the project root, and absolute path. Every module name is
+    /// sanitized into a valid Rust identifier, and two shaders whose modules would then have the same path are an
+    /// error, as is a shader whose module would also need to hold the modules of a directory.
+    pub(crate) fn new(
+        shaders: &[(String, PathBuf)],
+        nested: bool,
+        search_paths: &SearchPaths,
+    ) -> Result<Self, GenerateError> {
+        // The same file may be given under different names
+        let mut seen = HashSet::new();
+        let files = shaders
+            .iter()
+            .filter(|(_, file)| seen.insert(file))
+            .map(|(_, file)| {
+                let (dir, relative) = search_paths
+                    .relative_path(file)
+                    .map(|(dir, relative)| (Some(dir), relative))
+                    .unwrap_or_else(|| (None, file.file_name().map_or(file.as_path(), Path::new)));
+                (file.clone(), dir, relative.to_path_buf())
+            })
+            .collect::<Vec<_>>();
+        let mut paths = if nested {
+            nested_paths(&files)
+        } else {
+            flat_names(&files)
+                .into_iter()
+                .map(|(file, name)| (file, vec![name]))
+                .collect()
+        };
+
+        for path in paths.values_mut() {
+            *path = path.iter().map(|name| sanitize_ident(name)).collect();
+        }
+        let mut owners = HashMap::<Vec<String>, (&str, &PathBuf)>::new();
+        for (shader, file) in shaders {
+            let path = &paths[file];
+            if let Some((other, other_file)) = owners.insert(path.clone(), (shader, file)) {
+                if other_file != file {
+                    return Err(GenerateError::Codegen {
+                        shader: shader.clone(),
+                        message: format!(
+                            "`{}` and `{}` would both generate the module `{}` - rename one of them",
+                            other,
+                            shader,
+                            path.join("::")
+                        ),
+                    });
+                }
+            }
+        }
+
+        // A shader's module holds modules generated for it, such as `types`, so can't hold other shaders' modules too
+        for (shader, file) in shaders {
+            let path = &paths[file];
+            let parent =
+                (1..path.len()).find_map(|len| owners.get(&path[..len]).map(|(parent, _)| parent));
+            if let Some(parent) = parent {
+                return Err(GenerateError::Codegen {
+                    shader: shader.clone(),
+                    message: format!(
+                        "the module `{}` for `{}` would be nested within the module generated for `{}` - rename \
+                         one of them",
+                        path.join("::"),
+                        shader,
+                        parent
+                    ),
+                });
+            }
+        }
+
+        Ok(Self {
+            paths,
+            ..Default::default()
+        })
+    }
+
+    /// Gives the path of module names to a shader's module.
+    pub(crate) fn module_path(&self, file: &Path) -> Option<&[String]> {
+        self.paths.get(file).map(Vec::as_slice)
+    }
+
+    /// Hashes the path to every module and every exported and renamed struct, which is all that a module's items
+    /// need to know about the others.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut paths = self.paths.iter().collect::<Vec<_>>();
+        paths.sort();
+        let mut exported_structs = self.exported_structs.iter().collect::<Vec<_>>();
+        exported_structs.sort();
+        let mut renamed_structs = self.renamed_structs.iter().collect::<Vec<_>>();
+        renamed_structs.sort();
+        crate::cache::hash(&(paths, exported_structs, renamed_structs))
+    }
+
+    /// Records that a shader exports a struct, so that its module holds the struct.
+    pub(crate) fn export_struct(&mut self, file: &Path, struct_name: &str) {
+        self.exported_structs
+            .insert((file.to_path_buf(), struct_name.to_owned()));
+    }
+
+    /// Checks whether a shader's module holds one of its structs.
+    pub(crate) fn exports_struct(&self, file: &Path, struct_name: &str) -> bool {
+        self.exported_structs
+            .contains(&(file.to_path_buf(), struct_name.to_owned()))
+    }
+
+    /// Records that an exported struct of a shader has been given a different Rust name.
+    pub(crate) fn rename_struct(&mut self, file: &Path, struct_name: &str, rust_name: &str) {
+        self.renamed_structs.insert(
+            (file.to_path_buf(), struct_name.to_owned()),
+            rust_name.to_owned(),
+        );
+    }
+
+    /// Gives the Rust name of an exported struct of a shader.
+    pub(crate) fn struct_name<'a>(&'a self, file: &Path, struct_name: &'a str) -> &'a str {
+        self.renamed_structs
+            .get(&(file.to_path_buf(), struct_name.to_owned()))
+            .map_or(struct_name, String::as_str)
+    }
+}
+
+/// Turns a name into a valid Rust identifier. Characters that cannot appear in identifiers become underscores,
+/// names starting with a digit are prefixed with an underscore, and keywords become raw identifiers,
+/// e.g. `2d-sprite` becomes `_2d_sprite` and `type` becomes `r#type`.
+pub(crate) fn sanitize_ident(name: &str) -> String {
+    let mut ident = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    if ident.is_empty() || ident == "_" || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+
+    // Anything left that isn't an identifier is a keyword
+    if syn::parse_str::<syn::Ident>(&ident).is_ok() {
+        return ident;
+    }
+    let raw = format!("r#{}", ident);
+    if syn::parse_str::<syn::Ident>(&raw).is_ok() {
+        return raw;
+    }
+    // Keywords such as `self` and `crate` cannot be raw identifiers
+    ident + "_"
+}
+
+/// Gives the name of a shader file, without the `.wgsl` extension.
+pub(crate) fn file_stem(file: &Path) -> String {
+    file.file_stem()
+        .expect("shader paths are files")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Names each file by its stem, resolving collisions by extending names with parent directories. Files are given
+/// by their absolute path, the directory they were found within and their path relative to that directory, which
+/// only the directories of are used.
+fn flat_names(files: &[(PathBuf, Option<PathBuf>, PathBuf)]) -> HashMap<PathBuf, String> {
+    let mut names = files
+        .iter()
+        .map(|(file, _, relative)| (file_stem(file), 1usize, file, relative))
+        .collect::<Vec<_>>();
+
+    // Extended names may collide with names that were already distinct, e.g. `post/pbr.wgsl` with `pbr_post.wgsl`, so
+    // every name is checked again after each extension
+    loop {
+        let mut counts = HashMap::<String, usize>::new();
+        for (name, _, _, _) in &names {
+            *counts.entry(name.clone()).or_default() += 1;
+        }
+        if counts.values().all(|count| *count == 1) {
+            break;
+        }
+
+        for (i, (name, components, _, relative)) in names.iter_mut().enumerate() {
+            if counts[name.as_str()] == 1 {
+                continue;
+            }
+
+            let extra_component = relative
+                .components()
+                .rev()
+                .nth(*components)
+                .map(|component| component.as_os_str().to_string_lossy().into_owned());
+            match extra_component {
+                Some(extra_component) => *name = format!("{}_{}", name, extra_component),
+                None => name.push_str(&i.to_string()),
+            }
+            *components += 1;
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|(name, _, file, _)| (file.clone(), name))
+        .collect()
+}
+
+/// Gives each file a path of modules mirroring its location relative to the deepest directory holding every file
+/// found within the same directory. Files are given by their absolute path, the directory they were found within, if
+/// any, and their path relative to that directory.
+fn nested_paths(files: &[(PathBuf, Option<PathBuf>, PathBuf)]) -> HashMap<PathBuf, Vec<String>> {
+    let directories = |relative: &Path| {
+        relative
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    // The directories leading to every file found within each directory
+    let mut common = HashMap::<&Option<PathBuf>, Vec<String>>::new();
+    for (_, dir, relative) in files {
+        let directories = directories(relative);
+        common
+            .entry(dir)
+            .and_modify(|common| {
+                let len = common
+                    .iter()
+                    .zip(&directories)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                common.truncate(len);
+            })
+            .or_insert(directories);
+    }
+
+    files
+        .iter()
+        .map(|(file, dir, relative)| {
+            let mut path = directories(relative).split_off(common[dir].len());
+            path.push(file_stem(file));
+            (file.clone(), path)
+        })
+        .collect()
+}
+
+/// Combines the items of modules at given paths into a tree of nested modules, merging modules with a common prefix.
+/// The order of modules is preserved, with each parent module placed where its first child was.
+pub(crate) fn nest_modules(modules: Vec<(Vec<syn::Ident>, Vec<syn::Item>)>) -> Vec<syn::Item> {
+    let mut order = Vec::<syn::Ident>::new();
+    let mut children = HashMap::<syn::Ident, (Vec<syn::Item>, Vec<_>)>::new();
+    for (mut path, items) in modules {
+        if path.is_empty() {
+            continue;
+        }
+        let first = path.remove(0);
+        let (own_items, nested) = children.entry(first.clone()).or_insert_with(|| {
+            order.push(first.clone());
+            Default::default()
+        });
+        if path.is_empty() {
+            own_items.extend(items);
+        } else {
+            nested.push((path, items));
+        }
+    }
+
+    order
+        .into_iter()
+        .map(|name| {
+            let (mut items, nested) = children.remove(&name).expect("all names were inserted");
+            items.extend(nest_modules(nested));
+            syn::parse_quote! {
+                pub mod #name {
+                    #(#items)*
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn layout(files: &[&str], nested: bool) -> Result<ModuleLayout, GenerateError> {
+        let search_paths = SearchPaths::new(
+            PathBuf::from("/project"),
+            &[PathBuf::from("/shared")],
+            &BTreeMap::new(),
+        );
+        let shaders = files
+            .iter()
+            .map(|file| (file.to_string(), PathBuf::from(file)))
+            .collect::<Vec<_>>();
+        ModuleLayout::new(&shaders, nested, &search_paths)
+    }
+
+    fn module_path(layout: &ModuleLayout, file: &str) -> String {
+        layout.module_path(Path::new(file)).unwrap().join("::")
+    }
+
+    #[test]
+    fn nested_paths_are_relative_to_common_directories() {
+        let layout = layout(
+            &[
+                "/project/src/shaders/lighting/pbr.wgsl",
+                "/project/src/shaders/lighting/shadow.wgsl",
+                "/project/src/shaders/main.wgsl",
+                "/shared/lib/noise/simplex.wgsl",
+                "/shared/lib/utils.wgsl",
+            ],
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            module_path(&layout, "/project/src/shaders/lighting/pbr.wgsl"),
+            "lighting::pbr"
+        );
+        assert_eq!(
+            module_path(&layout, "/project/src/shaders/lighting/shadow.wgsl"),
+            "lighting::shadow"
+        );
+        assert_eq!(
+            module_path(&layout, "/project/src/shaders/main.wgsl"),
+            "main"
+        );
+        assert_eq!(
+            module_path(&layout, "/shared/lib/noise/simplex.wgsl"),
+            "noise::simplex"
+        );
+        assert_eq!(module_path(&layout, "/shared/lib/utils.wgsl"), "utils");
+    }
+
+    #[test]
+    fn flat_names_only_extend_with_directories_within_project() {
+        let layout = layout(&["/project/a/utils.wgsl", "/project/utils.wgsl"], false).unwrap();
+
+        assert_eq!(module_path(&layout, "/project/a/utils.wgsl"), "utils_a");
+        assert_eq!(module_path(&layout, "/project/utils.wgsl"), "utils1");
+    }
+
+    #[test]
+    fn flat_names_are_extended_until_unique() {
+        let layout = layout(
+            &[
+                "/project/pbr_post.wgsl",
+                "/project/post/pbr.wgsl",
+                "/project/lighting/pbr.wgsl",
+            ],
+            false,
+        )
+        .unwrap();
+
+        let names = [
+            "/project/pbr_post.wgsl",
+            "/project/post/pbr.wgsl",
+            "/project/lighting/pbr.wgsl",
+        ]
+        .map(|file| module_path(&layout, file));
+        assert_eq!(names[2], "pbr_lighting");
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), 3, "{:?}", names);
+    }
+
+    #[test]
+    fn same_file_under_different_names_is_one_module() {
+        let search_paths = SearchPaths::new(PathBuf::from("/project"), &[], &BTreeMap::new());
+        let shaders = [
+            ("type.wgsl".to_owned(), PathBuf::from("/project/type.wgsl")),
+            (
+                "./type.wgsl".to_owned(),
+                PathBuf::from("/project/type.wgsl"),
+            ),
+        ];
+        let layout = ModuleLayout::new(&shaders, false, &search_paths).unwrap();
+
+        assert_eq!(module_path(&layout, "/project/type.wgsl"), "r#type");
+    }
+
+    #[test]
+    fn same_module_path_is_an_error() {
+        assert!(layout(
+            &["/project/my-shader.wgsl", "/project/my_shader.wgsl"],
+            false
+        )
+        .is_err());
+        assert!(layout(&["/project/a/b.wgsl", "/shared/a/b.wgsl"], true).is_err());
+    }
+
+    #[test]
+    fn shader_with_directory_of_same_name_is_an_error() {
+        assert!(layout(&["/project/post.wgsl", "/project/post/bloom.wgsl"], true).is_err());
+        assert!(layout(&["/project/post.wgsl", "/project/post/types.wgsl"], true).is_err());
+        assert!(layout(&["/project/post/a.wgsl", "/project/post/b.wgsl"], true).is_ok());
+    }
+
+    #[test]
+    fn exported_structs_are_tracked_per_file() {
+        let mut layout = layout(&["/project/a.wgsl", "/project/b.wgsl"], false).unwrap();
+        let fingerprint = layout.fingerprint();
+        layout.export_struct(Path::new("/project/a.wgsl"), "Data");
+
+        assert!(layout.exports_struct(Path::new("/project/a.wgsl"), "Data"));
+        assert!(!layout.exports_struct(Path::new("/project/b.wgsl"), "Data"));
+        assert!(!layout.exports_struct(Path::new("/project/a.wgsl"), "Other"));
+        assert_ne!(layout.fingerprint(), fingerprint);
+    }
+}
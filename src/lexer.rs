@@ -0,0 +1,266 @@
+use std::ops::Range;
+
+/// A preprocessor directive, such as `#import` or `#ifdef`, as naga_oil would see it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Directive<'a> {
+    /// The name following the `#`, such as `import`.
+    pub(crate) name: &'a str,
+    /// Everything following the name, up to the end of the line or a comment. Directives with unclosed braces, such
+    /// as `#import a.wgsl::{`, continue onto the following lines until they are closed.
+    pub(crate) args: &'a str,
+    /// The byte range of the arguments within the source.
+    pub(crate) args_span: Range<usize>,
+    /// The byte range of the whole directive within the source, from the `#`.
+    pub(crate) span: Range<usize>,
+}
+
+/// Finds every preprocessor directive in WGSL source, in order.
+///
+/// A directive is a `#` that is the first thing on its line other than whitespace and block comments, followed by a
+/// name. Anything within a `//` or `/* */` comment is skipped, including nested block comments.
+pub(crate) fn directives(source: &str) -> Vec<Directive<'_>> {
+    let bytes = source.as_bytes();
+    let mut directives = Vec::new();
+    let mut at_line_start = true;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => {
+                at_line_start = true;
+                i += 1;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = line_end(bytes, i),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = block_comment_end(bytes, i),
+            b'#' if at_line_start => {
+                let directive = directive(source, i);
+                i = directive.span.end;
+                at_line_start = false;
+                directives.push(directive);
+            }
+            byte => {
+                if !byte.is_ascii_whitespace() {
+                    at_line_start = false;
+                }
+                i += 1;
+            }
+        }
+    }
+
+    directives
+}
+
+/// Replaces every `//` and `/* */` comment in WGSL source with spaces, keeping newlines so that line numbers and
+/// byte positions are unchanged.
+pub(crate) fn strip_comments(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut stripped = String::with_capacity(source.len());
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let end = match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'/')) => line_end(bytes, i),
+            (b'/', Some(b'*')) => block_comment_end(bytes, i),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        stripped.push_str(&source[copied..i]);
+        for c in source[i..end].chars() {
+            match c {
+                '\n' => stripped.push('\n'),
+                c => stripped.push_str(&" ".repeat(c.len_utf8())),
+            }
+        }
+        copied = end;
+        i = end;
+    }
+    stripped.push_str(&source[copied..]);
+
+    stripped
+}
+
+/// Reads the directive starting with the `#` at the given position.
+fn directive(source: &str, start: usize) -> Directive<'_> {
+    let bytes = source.as_bytes();
+
+    let name_start = skip_horizontal_whitespace(bytes, start + 1);
+    let name_end = name_start
+        + bytes[name_start..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_alphanumeric() || **byte == b'_')
+            .count();
+
+    // Arguments run to the end of the line or a comment, but braces are matched across lines
+    let mut depth = 0usize;
+    let mut end = name_end;
+    while end < bytes.len() {
+        match bytes[end] {
+            b'\n' if depth == 0 => break,
+            b'/' if matches!(bytes.get(end + 1), Some(b'/' | b'*')) => {
+                if depth == 0 {
+                    break;
+                }
+                // Skip comments within braces
+                end = if bytes[end + 1] == b'/' {
+                    line_end(bytes, end)
+                } else {
+                    block_comment_end(bytes, end)
+                };
+            }
+            b'{' => {
+                depth += 1;
+                end += 1;
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                end += 1;
+            }
+            _ => end += 1,
+        }
+    }
+
+    let args_start = skip_horizontal_whitespace(bytes, name_end).min(end);
+    let args_end = args_start + source[args_start..end].trim_end().len();
+
+    Directive {
+        name: &source[name_start..name_end],
+        args: &source[args_start..args_end],
+        args_span: args_start..args_end,
+        span: start..end,
+    }
+}
+
+fn skip_horizontal_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && matches!(bytes[i], b' ' | b'\t' | b'\r') {
+        i += 1;
+    }
+    i
+}
+
+/// Gives the position of the newline ending the line containing the given position, or the end of the source.
+fn line_end(bytes: &[u8], i: usize) -> usize {
+    bytes[i..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(bytes.len(), |offset| i + offset)
+}
+
+/// Gives the position just after the end of the block comment starting at the given position, accounting for nested
+/// block comments as WGSL does. An unclosed comment runs to the end of the source.
+fn block_comment_end(bytes: &[u8], mut i: usize) -> usize {
+    let mut depth = 0usize;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gives the name and arguments of every directive in some source.
+    fn lex(source: &str) -> Vec<(&str, &str)> {
+        directives(source)
+            .into_iter()
+            .map(|directive| {
+                assert_eq!(&source[directive.args_span.clone()], directive.args);
+                assert!(source[directive.span.clone()].starts_with('#'));
+                (directive.name, directive.args)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn directives_at_line_starts() {
+        assert_eq!(
+            lex("#import a.wgsl\n  #ifdef A\n\t# define B 1\nfn main() {}\n#endif"),
+            [
+                ("import", "a.wgsl"),
+                ("ifdef", "A"),
+                ("define", "B 1"),
+                ("endif", "")
+            ]
+        );
+        // Only whitespace and block comments may come before a directive
+        assert_eq!(
+            lex("/* a */ #import a.wgsl\nlet x = 1; #import b.wgsl"),
+            [("import", "a.wgsl")]
+        );
+    }
+
+    #[test]
+    fn directives_in_comments_are_skipped() {
+        assert_eq!(
+            lex("// #import a.wgsl\n/* #import b.wgsl\n#import c.wgsl */\n#import d.wgsl"),
+            [("import", "d.wgsl")]
+        );
+        // Block comments nest
+        assert_eq!(
+            lex("/* /* */\n#import a.wgsl\n*/\n#import b.wgsl"),
+            [("import", "b.wgsl")]
+        );
+        assert_eq!(lex("/* unclosed\n#import a.wgsl"), []);
+    }
+
+    #[test]
+    fn arguments_end_at_comments() {
+        assert_eq!(
+            lex("#import a.wgsl // b.wgsl\n#import c.wgsl /* d.wgsl */"),
+            [("import", "a.wgsl"), ("import", "c.wgsl")]
+        );
+    }
+
+    #[test]
+    fn braces_continue_onto_following_lines() {
+        let source = "#import a.wgsl::{\n    b, // first\n    /* } */ c,\n}\n#import d.wgsl";
+        assert_eq!(
+            lex(source),
+            [
+                ("import", "a.wgsl::{\n    b, // first\n    /* } */ c,\n}"),
+                ("import", "d.wgsl")
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        assert_eq!(
+            lex("#import a.wgsl\r\n#ifdef A\r\n#import b.wgsl::{\r\n  c,\r\n}\r\n#endif\r\n"),
+            [
+                ("import", "a.wgsl"),
+                ("ifdef", "A"),
+                ("import", "b.wgsl::{\r\n  c,\r\n}"),
+                ("endif", "")
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        let source = "@compute // @vertex\nfn main() {} /* @fragment\n /* é */ */ x";
+        let stripped = strip_comments(source);
+        assert_eq!(stripped.len(), source.len());
+        assert_eq!(stripped.lines().count(), source.lines().count());
+        assert_eq!(
+            stripped.split_whitespace().collect::<Vec<_>>(),
+            ["@compute", "fn", "main()", "{}", "x"]
+        );
+    }
+}
@@ -1,23 +1,50 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(any(feature = "spirv", feature = "msl", feature = "hlsl", feature = "glsl"))]
+mod backends;
+mod bind_groups;
+mod cache;
+mod composer;
+mod defs;
+mod discovery;
 mod error;
 mod exports;
 mod files;
+mod generator;
 mod imports;
+mod layout;
+mod lexer;
 mod module;
+mod parallel;
+mod pipelines;
+mod preprocess;
+#[cfg(feature = "naga")]
+mod serialized;
 mod source;
+mod vertex;
 
-use std::{
-    collections::{HashMap, HashSet},
-    path::PathBuf,
-};
+use std::collections::HashSet;
 
-use naga_oil::compose::Composer;
 use naga_to_tokenstream::{ModuleToTokens, ModuleToTokensConfig};
 use quote::format_ident;
 use syn::parse_quote;
 
-use crate::{error::decompose_mangled_name, exports::Export, source::Sourcecode};
+use crate::{
+    error::decompose_mangled_name, exports::Export, generator::Composed, layout::ModuleLayout,
+};
+
+pub use crate::{
+    error::{GenerateError, GenerateErrors},
+    generator::{Generator, ImportedDefines},
+};
+#[cfg(feature = "glsl")]
+pub use naga::back::glsl;
+#[cfg(feature = "hlsl")]
+pub use naga::back::hlsl;
+#[cfg(feature = "msl")]
+pub use naga::back::msl;
+pub use naga::valid::{Capabilities, ValidationFlags};
+pub use naga_oil::compose::ShaderDefValue;
 
 #[derive(PartialEq, Eq)]
 pub struct VertexInput {
@@ -25,11 +52,16 @@ pub struct VertexInput {
     pub fields: Vec<(u32, naga::StructMember)>,
 }
 
+/// Gives the types used as inputs to a vertex entry point, along with the module that declared each, or `None` for
+/// types declared by the shader itself.
 fn vertex_input_types(
     vertex_entry: &naga::EntryPoint,
     module: &naga::Module,
-    module_name: &str,
-) -> Vec<(String, String)> {
+) -> Vec<(Option<String>, String)> {
+    // Individual `@location` arguments are gathered into a struct of their own
+    let location_inputs =
+        vertex::location_inputs_struct_name(vertex_entry).map(|name| (None, name));
+
     vertex_entry
         .function
         .arguments
@@ -42,34 +74,59 @@ fn vertex_input_types(
                     let original = arg_type.name.as_ref().unwrap();
                     match decompose_mangled_name(original) {
                         // Type is from another module
-                        Some((module, type_name)) => Some((module, type_name.to_string())),
+                        Some((module, type_name)) => Some((Some(module), type_name.to_string())),
                         // Type is from this module
-                        None => Some((module_name.to_string(), original.to_string())),
+                        None => Some((None, original.to_string())),
                     }
                 }
                 // An argument has to have a binding unless it is a structure.
                 _ => None,
             }
         })
+        .chain(location_inputs)
         .collect()
 }
 
+/// The module that types without a generated module to refer to are placed in while generating items, so that any
+/// generated item using one can be found.
+const UNRESOLVED_MODULE: &str = "__wgsl_oil_unresolved";
+
+/// Generates the items within the Rust module for a shader. `depth` gives the number of modules that the generated
+/// module is nested within, beyond the top level of the generated file.
 fn module_items(
-    source: &Sourcecode,
-    module: &naga::Module,
+    generator: &Generator,
+    build: &Composed,
     module_name: String,
     vertex_inputs: Option<HashSet<String>>,
-) -> Vec<syn::Item> {
+    depth: usize,
+    layout: &ModuleLayout,
+) -> Result<Vec<syn::Item>, GenerateError> {
+    let source = &build.sourcecode;
+    let codegen_error = |message| GenerateError::Codegen {
+        shader: source.name().to_owned(),
+        message,
+    };
+    exports::check_exported_functions(source.exports(), &build.module).map_err(codegen_error)?;
+    let module = vertex::add_location_inputs_structs(&build.module).map_err(codegen_error)?;
+    let module = module.as_ref();
     let mut items = Vec::new();
 
     // Convert to info about the module
     let mut structs_filter: HashSet<String> = source
         .exports()
         .iter()
-        .map(|export| match export {
-            Export::Struct { struct_name } => struct_name.clone(),
+        .filter_map(|export| match export {
+            Export::Struct { struct_name, .. } => Some(struct_name.clone()),
+            _ => None,
         })
+        .chain(
+            module
+                .entry_points
+                .iter()
+                .filter_map(vertex::location_inputs_struct_name),
+        )
         .collect();
+    let mut unresolved = Vec::new();
     let type_overrides = module
         .types
         .iter()
@@ -77,127 +134,139 @@ fn module_items(
             let original_name = t.name.clone()?;
             let (module, name) = decompose_mangled_name(&original_name)?;
             structs_filter.remove(&original_name);
-            let module = format_ident!("{}", module);
-            let name = format_ident!("{}", name);
+            // Refer to the module generated for the file that the type came from
+            let file = source
+                .imported_modules()
+                .iter()
+                .find(|(import_name, _)| *import_name == module)
+                .map(|(_, path)| path.as_path());
+            // The type is only in the file's module if the file exports it
+            let Some((module_path, file)) = file
+                .filter(|file| layout.exports_struct(file, name))
+                .and_then(|file| Some((layout.module_path(file)?, file)))
+            else {
+                // Only an error if the type is used by a generated item, checked once the items are generated
+                let unresolved_module = format_ident!("{}", UNRESOLVED_MODULE);
+                let placeholder = format_ident!("{}", original_name);
+                unresolved.push((original_name.clone(), module, name.to_owned()));
+                return Some((
+                    original_name,
+                    parse_quote! { #unresolved_module :: #placeholder },
+                ));
+            };
+            let module_path = module_path.iter().map(|module| format_ident!("{}", module));
+            let name = format_ident!("{}", layout.struct_name(file, name));
+            let supers = vec![<syn::Token![super]>::default(); 3 + depth];
             Some((
                 original_name,
-                parse_quote! { super :: super :: super :: #module :: types :: #name },
+                parse_quote! { #(#supers ::)* #(#module_path ::)* types :: #name },
             ))
         })
         .collect();
     let mut module_items = module.to_items(ModuleToTokensConfig {
         structs_filter: Some(structs_filter),
-        gen_bytemuck: cfg!(feature = "bytemuck"),
-        gen_glam: cfg!(feature = "glam"),
-        gen_encase: cfg!(feature = "encase"),
-        gen_naga: cfg!(feature = "naga"),
+        gen_bytemuck: generator.gen_bytemuck,
+        gen_glam: generator.gen_glam,
+        gen_encase: generator.gen_encase,
+        gen_naga: generator.gen_naga,
         type_overrides,
-        vertex_input_types: vertex_inputs,
+        vertex_input_types: vertex_inputs.clone(),
         module_name,
     });
+    exports::filter_exported_items(&mut module_items, source.exports(), module);
+    if !unresolved.is_empty() {
+        let generated = quote::quote! { #(#module_items)* }.to_string();
+        let used = unresolved.iter().find(|(original_name, _, _)| {
+            generated.contains(&format!("{} :: {}", UNRESOLVED_MODULE, original_name))
+        });
+        if let Some((_, module, name)) = used {
+            return Err(codegen_error(format!(
+                "the type `{}` from `{}` has no generated module to refer to; export it from its file with \
+                 `@export` so that a module is generated for it",
+                name, module
+            )));
+        }
+    }
+    if let Some(vertex_inputs) = vertex_inputs.filter(|_| generator.gen_wgpu) {
+        vertex::add_vertex_buffer_layouts(
+            &mut module_items,
+            module,
+            &vertex_inputs,
+            source.exports(),
+        )
+        .map_err(codegen_error)?;
+    }
+    exports::apply_export_options(&mut module_items, source.exports()).map_err(codegen_error)?;
     items.append(&mut module_items);
 
-    items
-}
-
-pub fn generate_from_entrypoints(paths: &[String]) -> String {
-    let project_root = PathBuf::from(
-        std::env::var("CARGO_MANIFEST_DIR").expect("code generation depends on cargo"),
-    );
+    if generator.gen_wgpu {
+        let bind_groups = bind_groups::bind_groups_module(module, &build.info, source.name())
+            .map_err(codegen_error)?;
+        items.extend(bind_groups);
+        items.extend(pipelines::pipelines_module(module, source.exports()));
+    }
 
-    let mut composer = Composer::default().with_capabilities(naga::valid::Capabilities::all());
-    let mut validator = naga::valid::Validator::new(
-        naga::valid::ValidationFlags::all(),
-        naga::valid::Capabilities::all(),
+    // Translated from the validated module, without any generated additions
+    #[cfg(any(feature = "spirv", feature = "msl", feature = "hlsl", feature = "glsl"))]
+    items.extend(
+        backends::backend_items(generator, &build.module, &build.info).map_err(codegen_error)?,
     );
-
-    let mut shader_defs = HashMap::new();
-    if cfg!(debug_assertions) {
-        shader_defs.insert(
-            "__DEBUG".to_string(),
-            naga_oil::compose::ShaderDefValue::Bool(true),
+    #[cfg(feature = "naga")]
+    if generator.embed_naga_module {
+        items.extend(
+            serialized::serialized_module_items(generator, &build.module, &build.info)
+                .map_err(codegen_error)?,
         );
     }
 
-    let mut vertex_input_type_names: HashMap<String, HashSet<String>> = Default::default();
+    Ok(items)
+}
 
-    let items = paths
-        .iter()
-        .map(|path| {
-            let module_name = PathBuf::from(path)
-                .file_stem()
-                .unwrap()
-                .to_string_lossy()
-                .into_owned();
-            // TODO: convert to above below handling
-            let mut sourcecode = Sourcecode::new(project_root.clone(), path).unwrap();
-
-            println!("cargo:rerun-if-changed={}", path);
-            for p in sourcecode.relative_dependents() {
-                println!("cargo:rerun-if-changed={}", p.to_str().unwrap());
-            }
+/// Generates Rust modules for each of the given shader files, relative to `CARGO_MANIFEST_DIR`.
+///
+/// Equivalent to `Generator::new().entrypoints(paths).generate()`; see [`Generator`] to configure generation.
+///
+/// # Panics
+///
+/// Panics if any shader fails to generate; use [`Generator::generate`] to handle errors instead.
+pub fn generate_from_entrypoints(paths: &[String]) -> String {
+    Generator::new()
+        .entrypoints(paths.iter().cloned())
+        .generate()
+        .unwrap_or_else(|errors| panic!("{}", errors))
+}
 
-            let module = sourcecode
-                .compose(&mut composer, shader_defs.clone())
-                .map_err(|e| (module_name.clone(), e))?;
-            // TODO: convert to above error handling
-            validator
-                .validate(&module)
-                .expect("Shader module validation failed");
+/// The name of the file within `OUT_DIR` that [`generate_to_out_dir`] and [`Generator::write_to_out_dir`] write to.
+pub const OUT_DIR_FILE_NAME: &str = "generate_wgsl_oil.rs";
 
-            module
-                .entry_points
-                .iter()
-                .filter(|e| e.stage == naga::ShaderStage::Vertex)
-                // find the (possibly imported) types used as vertex inputs
-                .flat_map(|e| vertex_input_types(e, &module, &module_name).into_iter())
-                // record the type name under the source's module
-                .for_each(|(module_name, type_name)| {
-                    vertex_input_type_names
-                        .entry(module_name)
-                        .or_default()
-                        .insert(type_name);
-                });
-
-            Ok((sourcecode, module, module_name))
-        })
-        .collect::<Vec<_>>()
-        .into_iter()
-        .map(|res| {
-            let (sourcecode, module, module_name) = match res {
-                Ok(x) => x,
-                Err((module_name, e)) => {
-                    let name = format_ident!("{}", module_name);
-                    return parse_quote! {
-                        pub mod #name {
-                            compile_error!(#e);
-                        }
-                    };
-                }
-            };
-            let name = format_ident!("{}", module_name);
-            let vertex_inputs = vertex_input_type_names.remove(module_name.as_str());
-            let mod_items = module_items(&sourcecode, &module, module_name, vertex_inputs);
-            parse_quote! {
-                pub mod #name {
-                    #(#mod_items)*
-                }
-            }
-        })
-        .collect();
-    #[cfg(feature = "prettyplease")]
-    {
-        prettyplease::unparse(&syn::File {
-            items,
-            shebang: None,
-            attrs: vec![],
-        })
-    }
-    #[cfg(not(feature = "prettyplease"))]
-    {
-        let result = quote::quote! {
-            #(#items)*
-        };
-        result.to_string()
-    }
+/// Generates Rust modules for each of the given shader files, relative to `CARGO_MANIFEST_DIR`, writing them to a
+/// file in `OUT_DIR` to be included with [`include_wgsl_oil_generated!`]. The file is only rewritten if the
+/// generated code has changed.
+///
+/// Equivalent to `Generator::new().entrypoints(paths).write_to_out_dir()`.
+///
+/// # Panics
+///
+/// Panics if any shader fails to generate or the file cannot be written; use [`Generator::write_to_out_dir`] to
+/// handle errors instead.
+pub fn generate_to_out_dir(paths: &[String]) {
+    Generator::new()
+        .entrypoints(paths.iter().cloned())
+        .write_to_out_dir()
+        .unwrap_or_else(|errors| panic!("{}", errors));
+}
+
+/// Includes the code generated into `OUT_DIR` by [`generate_to_out_dir`] or [`Generator::write_to_out_dir`].
+///
+/// ```rust ignore
+/// mod shaders {
+///     generate_wgsl_oil::include_wgsl_oil_generated!();
+/// }
+/// ```
+#[macro_export]
+macro_rules! include_wgsl_oil_generated {
+    () => {
+        // Must match `OUT_DIR_FILE_NAME`, as `concat!` only accepts literals
+        include!(concat!(env!("OUT_DIR"), "/generate_wgsl_oil.rs"));
+    };
 }
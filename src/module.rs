@@ -1,16 +1,70 @@
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
     fmt::Display,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
+use naga_oil::compose::ShaderDefValue;
+
 use crate::{
     exports,
     files::AbsoluteWGSLFilePathBuf,
     imports::{self, ImportResolutionError},
+    lexer, preprocess,
 };
 
+/// The directories that imports are resolved within, other than the directory of the importing file.
+#[derive(Debug, Clone)]
+pub(crate) struct SearchPaths {
+    /// The absolute path of the project root, which entrypoints are also relative to.
+    project_root: PathBuf,
+    /// Further directories to look for imports in, in order.
+    import_paths: Vec<PathBuf>,
+    /// Directories that imports starting with `@name/` are resolved within, by name.
+    import_roots: BTreeMap<String, PathBuf>,
+}
+
+impl SearchPaths {
+    /// Creates search paths from directories relative to the project root.
+    pub(crate) fn new(
+        project_root: PathBuf,
+        import_paths: &[PathBuf],
+        import_roots: &BTreeMap<String, PathBuf>,
+    ) -> Self {
+        Self {
+            import_paths: import_paths
+                .iter()
+                .map(|path| project_root.join(path))
+                .collect(),
+            import_roots: import_roots
+                .iter()
+                .map(|(name, path)| (name.clone(), project_root.join(path)))
+                .collect(),
+            project_root,
+        }
+    }
+
+    pub(crate) fn project_root(&self) -> &Path {
+        &self.project_root
+    }
+
+    /// Gives the directory a file was found within, which is the project root if it is within it and otherwise the
+    /// import root or import path containing it, along with the path of the file relative to that directory.
+    pub(crate) fn relative_path<'a>(&self, file: &'a Path) -> Option<(PathBuf, &'a Path)> {
+        std::iter::once(&self.project_root)
+            .chain(self.import_roots.values())
+            .chain(&self.import_paths)
+            .find_map(|dir| {
+                // Shader paths are canonical, so the directories must be too
+                let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+                let relative = file.strip_prefix(&dir).ok()?;
+                Some((dir, relative))
+            })
+    }
+}
+
 /// A single requested import to a shader.
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub(crate) struct Module {
@@ -24,36 +78,83 @@ impl Module {
     }
 
     /// Given a path to a file and the string given to describe an import, tries to resolve the requested import file.
+    ///
+    /// Imports starting with `@name/` are resolved within the import root with that name. Other imports are tried
+    /// relative to the importing file, then the project root, then each import path in turn.
     pub(crate) fn resolve_module(
         importing: &Module,
-        project_root: &PathBuf,
+        search_paths: &SearchPaths,
         request_string: &str,
     ) -> Result<Self, ImportResolutionError> {
-        let mut searched = HashSet::new();
-
-        // Try interpret as relative to importing file
-        let parent = importing
-            .path
-            .parent()
-            .expect("every absolute path to a file has a parent");
-        let relative = parent.join(request_string);
-        searched.insert(relative.clone());
-        if relative.is_file() {
-            let path = relative.canonicalize().unwrap();
-            return Ok(Self::from_path(AbsoluteWGSLFilePathBuf::new(path)));
-        }
+        Self::resolve_module_with_misses(importing, search_paths, request_string)
+            .map(|(module, _)| module)
+    }
 
-        // Try interpret as relative to source root
-        let relative = project_root.join(request_string);
-        searched.insert(relative.clone());
-        if relative.is_file() {
-            let path = relative.canonicalize().unwrap();
-            return Ok(Self::from_path(AbsoluteWGSLFilePathBuf::new(path)));
+    /// Resolves an import as with [`Self::resolve_module`], also giving the candidates tried before the file found,
+    /// none of which existed. Creating any of them would change which file the import resolves to.
+    pub(crate) fn resolve_module_with_misses(
+        importing: &Module,
+        search_paths: &SearchPaths,
+        request_string: &str,
+    ) -> Result<(Self, Vec<PathBuf>), ImportResolutionError> {
+        let candidates = match request_string.strip_prefix('@') {
+            Some(named) => {
+                let (name, rest) = named.split_once('/').unwrap_or((named, ""));
+                let Some(root) = search_paths.import_roots.get(name) else {
+                    return Err(ImportResolutionError::UnknownRoot {
+                        requested: request_string.to_string(),
+                        importer: importing.to_owned(),
+                        line: None,
+                        root: name.to_string(),
+                    });
+                };
+                vec![root.join(rest)]
+            }
+            None => {
+                let parent = importing
+                    .path
+                    .parent()
+                    .expect("every absolute path to a file has a parent");
+                std::iter::once(parent)
+                    .chain(std::iter::once(search_paths.project_root.as_path()))
+                    .chain(search_paths.import_paths.iter().map(PathBuf::as_path))
+                    .map(|dir| dir.join(request_string))
+                    .collect()
+            }
+        };
+
+        let mut searched = Vec::new();
+        for candidate in candidates {
+            if candidate.is_file() {
+                let path = candidate
+                    .canonicalize()
+                    .map_err(|error| ImportResolutionError::Io {
+                        path: candidate.clone(),
+                        error,
+                    })?;
+                if path.extension() != Some(OsStr::new("wgsl")) {
+                    return Err(ImportResolutionError::NotWgsl {
+                        requested: request_string.to_string(),
+                        importer: importing.to_owned(),
+                        line: None,
+                        path,
+                    });
+                }
+                return Ok((
+                    Self::from_path(AbsoluteWGSLFilePathBuf::new(path)),
+                    searched,
+                ));
+            }
+            // The importing file's directory may also be the project root
+            if !searched.contains(&candidate) {
+                searched.push(candidate);
+            }
         }
 
         Err(ImportResolutionError::Unresolved {
             requested: request_string.to_string(),
             importer: importing.to_owned(),
+            line: None,
             searched,
         })
     }
@@ -61,26 +162,34 @@ impl Module {
     pub(crate) fn processed_source(
         &self,
         module_names: &HashMap<Module, String>,
-        project_root: &PathBuf,
-    ) -> String {
-        let source = self.read_to_string();
+        search_paths: &SearchPaths,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+    ) -> std::io::Result<String> {
+        let source = self.read_to_string()?;
         // Replace `@export` directives with equivalent whitespace
-        let (source, _) = exports::strip_exports(&source);
+        let source = exports::strip_exports(&source);
         // Replace `#import` names with substitutions
-        imports::replace_imports_in_source(&source, self, project_root, module_names)
+        Ok(imports::replace_imports_in_source(
+            &source,
+            self,
+            search_paths,
+            module_names,
+            shader_defs,
+        ))
+    }
+
+    /// Reads the `#define` directives of the file.
+    pub(crate) fn defines(&self) -> std::io::Result<HashMap<String, ShaderDefValue>> {
+        let source = self.read_to_string()?;
+        Ok(preprocess::defines(&lexer::directives(&source)))
     }
 
     pub(crate) fn path(&self) -> AbsoluteWGSLFilePathBuf {
         self.path.clone()
     }
 
-    pub(crate) fn read_to_string(&self) -> String {
-        std::fs::read_to_string(&*self.path).unwrap_or_else(|_| {
-            panic!(
-                "file `{}` exists but could not be read",
-                self.path.display()
-            )
-        })
+    pub(crate) fn read_to_string(&self) -> std::io::Result<String> {
+        std::fs::read_to_string(&*self.path)
     }
 
     /// Gets the name of the file, without the `.wgsl` extension.
@@ -0,0 +1,67 @@
+use std::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Gives the number of threads to use by default: the number of jobs cargo was given when running a build script,
+/// otherwise the available parallelism.
+pub(crate) fn default_threads() -> usize {
+    std::env::var("NUM_JOBS")
+        .ok()
+        .and_then(|jobs| jobs.parse::<NonZeroUsize>().ok())
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get)
+}
+
+/// Runs `job` on each of the given items, with a thread for each of the given workers, giving the results in the same
+/// order as the items regardless of which thread finished first.
+///
+/// Each thread is given its own worker to use for every item it takes, so that state such as caches can be kept
+/// between items and between calls.
+pub(crate) fn map_ordered<T, S, R>(
+    items: &[T],
+    workers: &mut [S],
+    job: impl Fn(&mut S, &T) -> R + Sync,
+) -> Vec<R>
+where
+    T: Sync,
+    S: Send,
+    R: Send,
+{
+    let threads = workers.len().min(items.len());
+    if threads <= 1 {
+        let state = workers.first_mut().expect("at least one worker is given");
+        return items.iter().map(|item| job(state, item)).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let job = &job;
+    let next = &next;
+    let mut results = std::thread::scope(|scope| {
+        let threads = workers[..threads]
+            .iter_mut()
+            .map(|state| {
+                scope.spawn(move || {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break results;
+                        };
+                        results.push((i, job(state, item)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        threads
+            .into_iter()
+            .flat_map(|thread| match thread.join() {
+                Ok(results) => results,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    results.into_iter().map(|(_, result)| result).collect()
+}
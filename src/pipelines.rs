@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+
+use quote::{format_ident, quote};
+use syn::parse_quote;
+
+use crate::{
+    exports::{self, Export},
+    layout::sanitize_ident,
+};
+
+/// The name of the generated module containing pipeline helpers for each entry point.
+const PIPELINES_MODULE_NAME: &str = "pipelines";
+
+/// Generates a module containing a module for each entry point of a shader, holding its name, stage and workgroup
+/// size as constants along with functions to fill in the `wgpu` pipeline state for it.
+///
+/// Only entry points that are exported are included if any entry points are exported, in the same way as the
+/// `entry_points` module. Gives `None` if there are no entry points.
+pub(crate) fn pipelines_module(
+    module: &naga::Module,
+    exports: &HashSet<Export>,
+) -> Option<syn::Item> {
+    let exported = exports::exported_entry_points(exports, module);
+
+    let entry_points = module
+        .entry_points
+        .iter()
+        .filter(|entry_point| exported.is_empty() || exported.contains(entry_point.name.as_str()))
+        .map(|entry_point| {
+            let module_name = format_ident!("{}", sanitize_ident(&entry_point.name));
+            let name = &entry_point.name;
+
+            let helpers = match entry_point.stage {
+                naga::ShaderStage::Vertex => quote! {
+                    pub const STAGE: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX;
+
+                    pub fn vertex_state<'a>(
+                        module: &'a wgpu::ShaderModule,
+                        buffers: &'a [wgpu::VertexBufferLayout<'a>],
+                    ) -> wgpu::VertexState<'a> {
+                        wgpu::VertexState {
+                            module,
+                            entry_point: Some(ENTRY_POINT),
+                            compilation_options: Default::default(),
+                            buffers,
+                        }
+                    }
+                },
+                naga::ShaderStage::Fragment => quote! {
+                    pub const STAGE: wgpu::ShaderStages = wgpu::ShaderStages::FRAGMENT;
+
+                    pub fn fragment_state<'a>(
+                        module: &'a wgpu::ShaderModule,
+                        targets: &'a [Option<wgpu::ColorTargetState>],
+                    ) -> wgpu::FragmentState<'a> {
+                        wgpu::FragmentState {
+                            module,
+                            entry_point: Some(ENTRY_POINT),
+                            compilation_options: Default::default(),
+                            targets,
+                        }
+                    }
+                },
+                naga::ShaderStage::Compute => {
+                    // Sizes given by overrides aren't known until the pipeline is created
+                    let fixed_size = match &entry_point.workgroup_size_overrides {
+                        Some(overrides) => overrides.iter().all(Option::is_none),
+                        None => true,
+                    };
+                    let workgroup_size = fixed_size.then(|| {
+                        let [x, y, z] = entry_point.workgroup_size;
+                        quote! { pub const WORKGROUP_SIZE: [u32; 3] = [#x, #y, #z]; }
+                    });
+
+                    quote! {
+                        pub const STAGE: wgpu::ShaderStages = wgpu::ShaderStages::COMPUTE;
+
+                        #workgroup_size
+
+                        pub fn compute_pipeline_descriptor<'a>(
+                            label: Option<&'a str>,
+                            layout: Option<&'a wgpu::PipelineLayout>,
+                            module: &'a wgpu::ShaderModule,
+                        ) -> wgpu::ComputePipelineDescriptor<'a> {
+                            wgpu::ComputePipelineDescriptor {
+                                label,
+                                layout,
+                                module,
+                                entry_point: Some(ENTRY_POINT),
+                                compilation_options: Default::default(),
+                                cache: None,
+                            }
+                        }
+                    }
+                }
+            };
+
+            quote! {
+                pub mod #module_name {
+                    pub const ENTRY_POINT: &str = #name;
+
+                    #helpers
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if entry_points.is_empty() {
+        return None;
+    }
+
+    let module_name = format_ident!("{}", PIPELINES_MODULE_NAME);
+    Some(parse_quote! {
+        pub mod #module_name {
+            #(#entry_points)*
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+
+    use super::*;
+
+    /// Gives the pipelines module generated for a shader.
+    fn pipelines(source: &str) -> Option<String> {
+        let exports = exports::parse_exports(source).unwrap();
+        let module = naga::front::wgsl::parse_str(&exports::strip_exports(source)).unwrap();
+        pipelines_module(&module, &exports).map(|item| item.to_token_stream().to_string())
+    }
+
+    #[test]
+    fn compute_entry_points() {
+        let source = "
+            @compute @workgroup_size(8, 4)
+            fn main() {}
+        ";
+
+        assert_eq!(
+            pipelines(source).unwrap(),
+            quote! {
+                pub mod pipelines {
+                    pub mod main {
+                        pub const ENTRY_POINT: &str = "main";
+
+                        pub const STAGE: wgpu::ShaderStages = wgpu::ShaderStages::COMPUTE;
+
+                        pub const WORKGROUP_SIZE: [u32; 3] = [8u32, 4u32, 1u32];
+
+                        pub fn compute_pipeline_descriptor<'a>(
+                            label: Option<&'a str>,
+                            layout: Option<&'a wgpu::PipelineLayout>,
+                            module: &'a wgpu::ShaderModule,
+                        ) -> wgpu::ComputePipelineDescriptor<'a> {
+                            wgpu::ComputePipelineDescriptor {
+                                label,
+                                layout,
+                                module,
+                                entry_point: Some(ENTRY_POINT),
+                                compilation_options: Default::default(),
+                                cache: None,
+                            }
+                        }
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn workgroup_sizes_given_by_overrides_are_left_out() {
+        let source = "
+            override size: u32 = 64;
+
+            @compute @workgroup_size(size)
+            fn main() {}
+        ";
+
+        let generated = pipelines(source).unwrap();
+        assert!(!generated.contains("WORKGROUP_SIZE"), "{}", generated);
+        assert!(
+            generated.contains("compute_pipeline_descriptor"),
+            "{}",
+            generated
+        );
+    }
+
+    #[test]
+    fn render_entry_points() {
+        let source = "
+            @vertex
+            fn vs_main() -> @builtin(position) vec4<f32> { return vec4(0.0); }
+
+            @fragment
+            fn in() -> @location(0) vec4<f32> { return vec4(1.0); }
+        ";
+
+        assert_eq!(
+            pipelines(source).unwrap(),
+            quote! {
+                pub mod pipelines {
+                    pub mod vs_main {
+                        pub const ENTRY_POINT: &str = "vs_main";
+
+                        pub const STAGE: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX;
+
+                        pub fn vertex_state<'a>(
+                            module: &'a wgpu::ShaderModule,
+                            buffers: &'a [wgpu::VertexBufferLayout<'a>],
+                        ) -> wgpu::VertexState<'a> {
+                            wgpu::VertexState {
+                                module,
+                                entry_point: Some(ENTRY_POINT),
+                                compilation_options: Default::default(),
+                                buffers,
+                            }
+                        }
+                    }
+                    pub mod r#in {
+                        pub const ENTRY_POINT: &str = "in";
+
+                        pub const STAGE: wgpu::ShaderStages = wgpu::ShaderStages::FRAGMENT;
+
+                        pub fn fragment_state<'a>(
+                            module: &'a wgpu::ShaderModule,
+                            targets: &'a [Option<wgpu::ColorTargetState>],
+                        ) -> wgpu::FragmentState<'a> {
+                            wgpu::FragmentState {
+                                module,
+                                entry_point: Some(ENTRY_POINT),
+                                compilation_options: Default::default(),
+                                targets,
+                            }
+                        }
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn only_exported_entry_points_are_included() {
+        let source = "
+            @export @compute @workgroup_size(1)
+            fn exported() {}
+
+            @compute @workgroup_size(1)
+            fn hidden() {}
+        ";
+
+        let generated = pipelines(source).unwrap();
+        assert!(generated.contains("pub mod exported"), "{}", generated);
+        assert!(!generated.contains("pub mod hidden"), "{}", generated);
+    }
+
+    #[test]
+    fn no_entry_points() {
+        assert_eq!(pipelines("fn helper() -> f32 { return 1.0; }"), None);
+    }
+}
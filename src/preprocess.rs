@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use naga_oil::compose::ShaderDefValue;
+
+use crate::lexer::{self, Directive};
+
+/// How far a branch of a conditional has been taken, mirroring naga_oil's preprocessor.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// The current branch is taken.
+    Active,
+    /// An earlier branch of the conditional was taken, so no later branch can be.
+    PreviouslyActive,
+    /// No branch of the conditional has been taken yet.
+    NotActive,
+}
+
+/// A directive that opens, continues or closes a conditional block.
+enum Conditional {
+    /// `#ifdef`, `#ifndef` or `#if`, possibly following `#else`, and whether its condition holds. A plain `#else`
+    /// is an `#else` branch whose condition always holds.
+    Branch { is_else: bool, condition: bool },
+    /// `#endif`.
+    EndIf,
+}
+
+/// Gives the byte ranges of the source that naga_oil would leave out when preprocessing it with the given shader defs,
+/// between the conditional directives of the source.
+///
+/// Gives `None` if the conditionals can't be evaluated, such as when an `#if` compares against a def that isn't
+/// defined or the conditionals are unbalanced, in which case naga_oil will report the problem when composing.
+pub(crate) fn inactive_ranges(
+    source_len: usize,
+    directives: &[Directive<'_>],
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Option<Vec<Range<usize>>> {
+    let mut scopes = vec![Scope::Active];
+    let mut ranges = Vec::new();
+    let mut region_start = 0;
+
+    for directive in directives {
+        let Some(conditional) = conditional(directive, shader_defs)? else {
+            continue;
+        };
+
+        let active = *scopes.last()? == Scope::Active;
+        if !active && region_start < directive.span.start {
+            ranges.push(region_start..directive.span.start);
+        }
+        region_start = directive.span.end;
+
+        match conditional {
+            Conditional::Branch {
+                is_else: false,
+                condition,
+            } => scopes.push(if active && condition {
+                Scope::Active
+            } else {
+                Scope::NotActive
+            }),
+            Conditional::Branch {
+                is_else: true,
+                condition,
+            } => {
+                let previous = scopes.pop()?;
+                let parent = *scopes.last()?;
+                scopes.push(if parent != Scope::Active {
+                    Scope::NotActive
+                } else if previous != Scope::NotActive {
+                    Scope::PreviouslyActive
+                } else if condition {
+                    Scope::Active
+                } else {
+                    Scope::NotActive
+                });
+            }
+            Conditional::EndIf => {
+                scopes.pop();
+                if scopes.is_empty() {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if scopes.len() != 1 {
+        return None;
+    }
+    if region_start < source_len && *scopes.last()? != Scope::Active {
+        ranges.push(region_start..source_len);
+    }
+
+    Some(ranges)
+}
+
+/// Reads a conditional directive, giving `Some(None)` for directives that aren't conditionals and `None` if a
+/// condition can't be evaluated.
+fn conditional(
+    directive: &Directive<'_>,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Option<Option<Conditional>> {
+    let (is_else, name, args) = match directive.name {
+        "else" => match directive.args.split_once(char::is_whitespace) {
+            Some((name @ ("ifdef" | "ifndef" | "if"), args)) => (true, name, args.trim_start()),
+            _ => (true, "else", ""),
+        },
+        name if name.starts_with("else") => (true, "else", ""),
+        name if name.starts_with("endif") => return Some(Some(Conditional::EndIf)),
+        name => (false, name, directive.args),
+    };
+
+    let condition = match name {
+        "ifdef" => shader_defs.contains_key(ident(args)?),
+        "ifndef" => !shader_defs.contains_key(ident(args)?),
+        "if" => compare(args, shader_defs)?,
+        "else" => true,
+        _ => return Some(None),
+    };
+
+    Some(Some(Conditional::Branch { is_else, condition }))
+}
+
+/// Gives the identifier at the start of some directive arguments.
+fn ident(args: &str) -> Option<&str> {
+    let len = args
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(args.len());
+    (len > 0).then(|| &args[..len])
+}
+
+/// Evaluates the condition of an `#if`, such as `MAX_LIGHTS >= 4`.
+fn compare(args: &str, shader_defs: &HashMap<String, ShaderDefValue>) -> Option<bool> {
+    let name = ident(args)?;
+    let rest = args[name.len()..].trim_start();
+    let op_len = rest
+        .find(|c: char| !matches!(c, '=' | '!' | '<' | '>'))
+        .unwrap_or(rest.len());
+    let (op, rest) = rest.split_at(op_len);
+    let rest = rest.trim_start();
+    let value_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .unwrap_or(rest.len());
+    let value = &rest[..value_len];
+
+    fn act_on<T: Ord>(a: T, b: T, op: &str) -> Option<bool> {
+        match op {
+            "==" => Some(a == b),
+            "!=" => Some(a != b),
+            ">" => Some(a > b),
+            ">=" => Some(a >= b),
+            "<" => Some(a < b),
+            "<=" => Some(a <= b),
+            _ => None,
+        }
+    }
+
+    match shader_defs.get(name)? {
+        ShaderDefValue::Bool(def) => act_on(*def, value.parse().ok()?, op),
+        ShaderDefValue::Int(def) => act_on(*def, value.parse().ok()?, op),
+        ShaderDefValue::UInt(def) => act_on(*def, value.parse().ok()?, op),
+    }
+}
+
+/// Reads the `#define` directives of a top-level shader, which naga_oil applies to the whole shader and its imports
+/// wherever they appear.
+pub(crate) fn defines(directives: &[Directive<'_>]) -> HashMap<String, ShaderDefValue> {
+    directives
+        .iter()
+        .filter(|directive| directive.name == "define")
+        .filter_map(|directive| {
+            let name = ident(directive.args)?;
+            let value = directive.args[name.len()..].trim_start();
+            let value_len = value
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(value.len());
+            let value = &value[..value_len];
+
+            let value = if value.is_empty() {
+                ShaderDefValue::Bool(true)
+            } else if let Ok(value) = value.parse::<u32>() {
+                ShaderDefValue::UInt(value)
+            } else if let Ok(value) = value.parse::<i32>() {
+                ShaderDefValue::Int(value)
+            } else if let Ok(value) = value.parse::<bool>() {
+                ShaderDefValue::Bool(value)
+            } else {
+                // naga_oil reports the invalid value when composing
+                ShaderDefValue::Bool(false)
+            };
+            Some((name.to_owned(), value))
+        })
+        .collect()
+}
+
+/// Replaces the given ranges of the source with spaces, keeping newlines so that every position within the source
+/// stays on the same line and at the same byte offset.
+pub(crate) fn blank_ranges(source: &str, ranges: &[Range<usize>]) -> String {
+    let mut blanked = source.to_owned();
+    for range in ranges {
+        let replacement = source[range.clone()]
+            .chars()
+            .map(|c| match c {
+                '\n' | '\r' => c.to_string(),
+                c => " ".repeat(c.len_utf8()),
+            })
+            .collect::<String>();
+        blanked.replace_range(range.clone(), &replacement);
+    }
+    blanked
+}
+
+/// Removes the `#define` directives of a source file, keeping every position within the source the same.
+pub(crate) fn strip_defines(source: &str) -> String {
+    let defines = lexer::directives(source)
+        .into_iter()
+        .filter(|directive| directive.name == "define")
+        .map(|directive| directive.span)
+        .collect::<Vec<_>>();
+    blank_ranges(source, &defines)
+}
+
+/// Applies the `#define` directives of an imported file within that file alone, as naga_oil only accepts defines in
+/// top-level shaders.
+///
+/// The defines are removed, every conditional is evaluated with the given shader defs, which should include the
+/// file's defines, and uses of the defines as `#NAME` or `#{NAME}` are replaced by their values. If the conditionals
+/// can't be evaluated they are left for naga_oil to report.
+pub(crate) fn apply_scoped_defines(
+    source: &str,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+    defines: &HashMap<String, ShaderDefValue>,
+) -> String {
+    let directives = lexer::directives(source);
+    let mut removed = directives
+        .iter()
+        .filter(|directive| directive.name == "define")
+        .map(|directive| directive.span.clone())
+        .collect::<Vec<_>>();
+    if let Some(inactive) = inactive_ranges(source.len(), &directives, shader_defs) {
+        removed.extend(inactive);
+        removed.extend(
+            directives
+                .iter()
+                .filter(|directive| matches!(conditional(directive, shader_defs), Some(Some(_))))
+                .map(|directive| directive.span.clone()),
+        );
+    }
+    let source = blank_ranges(source, &removed);
+
+    // Directives such as `#import` are left alone
+    let directives = lexer::directives(&source)
+        .into_iter()
+        .filter(|directive| !defines.contains_key(directive.name))
+        .map(|directive| directive.span.start)
+        .collect::<HashSet<_>>();
+    let mut substituted = String::with_capacity(source.len());
+    let mut rest = 0;
+    for (i, _) in source.match_indices('#') {
+        if i < rest || directives.contains(&i) {
+            continue;
+        }
+        let after = &source[i + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.split_once('}') {
+                Some((name, _)) => (name.trim(), name.len() + 2),
+                None => continue,
+            },
+            None => {
+                let name = ident(after.trim_start()).unwrap_or_default();
+                (name, after.len() - after.trim_start().len() + name.len())
+            }
+        };
+        let Some(value) = defines.get(name) else {
+            continue;
+        };
+
+        substituted.push_str(&source[rest..i]);
+        substituted.push_str(&match value {
+            ShaderDefValue::Bool(value) => value.to_string(),
+            ShaderDefValue::Int(value) => value.to_string(),
+            ShaderDefValue::UInt(value) => value.to_string(),
+        });
+        rest = i + 1 + len;
+    }
+    substituted.push_str(&source[rest..]);
+
+    substituted
+}
+
+#[cfg(test)]
+mod tests {
+    use naga_oil::compose::preprocess::Preprocessor;
+
+    use super::*;
+
+    /// Gives the lines of the source holding a `kept` marker that naga_oil keeps, or `None` if it fails.
+    fn naga_oil_kept(
+        preprocessor: &Preprocessor,
+        source: &str,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+    ) -> Option<Vec<usize>> {
+        let output = preprocessor.preprocess(source, shader_defs).ok()?;
+        Some(
+            output
+                .preprocessed_source
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| line.contains("kept"))
+                .map(|(i, _)| i)
+                .collect(),
+        )
+    }
+
+    /// Gives the lines of the source holding a `kept` marker outside of the inactive ranges, or `None` if they can't
+    /// be found.
+    fn kept(source: &str, shader_defs: &HashMap<String, ShaderDefValue>) -> Option<Vec<usize>> {
+        let directives = lexer::directives(source);
+        let inactive = inactive_ranges(source.len(), &directives, shader_defs)?;
+        Some(
+            blank_ranges(source, &inactive)
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| line.contains("kept"))
+                .map(|(i, _)| i)
+                .collect(),
+        )
+    }
+
+    fn defs(defs: &[(&str, ShaderDefValue)]) -> HashMap<String, ShaderDefValue> {
+        defs.iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn inactive_ranges_match_naga_oil() {
+        let preprocessor = Preprocessor::default();
+        let sources = [
+            "kept\n#ifdef A\nkept\n#else\nkept\n#endif\nkept",
+            "#ifndef A\nkept\n#else ifdef B\nkept\n#else\nkept\n#endif",
+            "#ifdef A\n#ifdef B\nkept\n#else\nkept\n#endif\n#else ifndef B\nkept\n#endif",
+            "#if N >= 4\nkept\n#else if N == 2\nkept\n#else if FLAG == true\nkept\n#endif",
+            "#if I < -1\nkept\n#else\n#ifdef A\nkept\n#endif\nkept\n#endif",
+            "  # ifdef A\n  kept\n  #  else\nkept\n  #endif // trailing",
+            "#ifdef A\nkept\n#endif\r\n#ifdef B\r\nkept\r\n#endif\r\n",
+            // Conditionals within comments are ignored
+            "/* #ifdef A */\nkept\n// #endif\n#ifdef A\nkept\n#endif",
+            // Unbalanced
+            "#ifdef A\nkept",
+            "kept\n#endif",
+            "#else\nkept\n#endif",
+            "#ifdef A\n#endif\n#endif",
+            // `#if` on an undefined def or with a mismatched value
+            "#if UNDEFINED == 1\nkept\n#endif",
+            "#ifdef A\n#if UNDEFINED == 1\nkept\n#endif\n#endif",
+            "#if N == true\nkept\n#endif",
+            "#if FLAG == 1\nkept\n#endif",
+            "#if N ~ 1\nkept\n#endif",
+        ];
+        let def_sets = [
+            defs(&[
+                ("N", ShaderDefValue::UInt(2)),
+                ("I", ShaderDefValue::Int(-2)),
+                ("FLAG", ShaderDefValue::Bool(true)),
+            ]),
+            defs(&[
+                ("A", ShaderDefValue::Bool(true)),
+                ("N", ShaderDefValue::UInt(4)),
+                ("I", ShaderDefValue::Int(0)),
+                ("FLAG", ShaderDefValue::Bool(false)),
+            ]),
+            defs(&[
+                ("A", ShaderDefValue::Bool(true)),
+                ("B", ShaderDefValue::Bool(true)),
+                ("N", ShaderDefValue::UInt(0)),
+                ("I", ShaderDefValue::Int(-1)),
+                ("FLAG", ShaderDefValue::Bool(true)),
+            ]),
+        ];
+
+        for source in sources {
+            for shader_defs in &def_sets {
+                assert_eq!(
+                    kept(source, shader_defs),
+                    naga_oil_kept(&preprocessor, source, shader_defs),
+                    "{:?} with {:?}",
+                    source,
+                    shader_defs
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn comparisons_match_naga_oil() {
+        let preprocessor = Preprocessor::default();
+        let values = [
+            ShaderDefValue::Bool(true),
+            ShaderDefValue::Bool(false),
+            ShaderDefValue::Int(-3),
+            ShaderDefValue::Int(3),
+            ShaderDefValue::UInt(0),
+            ShaderDefValue::UInt(3),
+        ];
+        let ops = ["==", "!=", "<", "<=", ">", ">=", "=", "<>"];
+        let operands = ["true", "false", "-3", "0", "3", "4", "x"];
+
+        for value in values {
+            let shader_defs = defs(&[("V", value)]);
+            for op in ops {
+                for operand in operands {
+                    let args = format!("V {} {}", op, operand);
+                    let source = format!("#if {}\nkept\n#endif", args);
+                    let expected = naga_oil_kept(&preprocessor, &source, &shader_defs)
+                        .map(|kept| !kept.is_empty());
+                    assert_eq!(
+                        compare(&args, &shader_defs),
+                        expected,
+                        "{} with {:?}",
+                        args,
+                        value
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conditionals_are_recognised_as_naga_oil_does() {
+        let shader_defs = defs(&[("A", ShaderDefValue::Bool(true))]);
+        let cases = [
+            ("#ifdef A", Some(Some((false, true)))),
+            ("#ifndef A", Some(Some((false, false)))),
+            ("#else ifdef B", Some(Some((true, false)))),
+            ("#else ifndef B", Some(Some((true, true)))),
+            ("#else", Some(Some((true, true)))),
+            ("#elseif B", Some(Some((true, true)))),
+            ("#if B == 1", None),
+            ("#ifdef", None),
+            ("#endif", Some(None)),
+            ("#import a.wgsl", Some(None)),
+        ];
+
+        for (source, expected) in cases {
+            let directives = lexer::directives(source);
+            let actual =
+                conditional(&directives[0], &shader_defs).map(|conditional| match conditional {
+                    Some(Conditional::Branch { is_else, condition }) => Some((is_else, condition)),
+                    Some(Conditional::EndIf) | None => None,
+                });
+            assert_eq!(actual, expected, "{}", source);
+        }
+    }
+}
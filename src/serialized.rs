@@ -0,0 +1,47 @@
+use syn::parse_quote;
+
+use crate::generator::Generator;
+
+/// Generates constants holding the validated module and its info serialized with `bincode`, along with functions to
+/// deserialize them, so that the module can be given to wgpu without parsing any shader source at runtime.
+pub(crate) fn serialized_module_items(
+    generator: &Generator,
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+) -> Result<Vec<syn::Item>, String> {
+    let module_bytes = bincode::serialize(module)
+        .map_err(|e| format!("failed to serialize naga module: {}", e))?;
+    let info_bytes = bincode::serialize(info)
+        .map_err(|e| format!("failed to serialize naga module info: {}", e))?;
+    let module_bytes = syn::LitByteStr::new(&module_bytes, proc_macro2::Span::call_site());
+    let info_bytes = syn::LitByteStr::new(&info_bytes, proc_macro2::Span::call_site());
+
+    let mut items: Vec<syn::Item> = vec![
+        parse_quote! {
+            pub const NAGA_MODULE: &[u8] = #module_bytes;
+        },
+        parse_quote! {
+            pub const NAGA_MODULE_INFO: &[u8] = #info_bytes;
+        },
+        parse_quote! {
+            pub fn naga_module() -> naga::Module {
+                bincode::deserialize(NAGA_MODULE).expect("embedded naga module is valid")
+            }
+        },
+        parse_quote! {
+            pub fn naga_module_info() -> naga::valid::ModuleInfo {
+                bincode::deserialize(NAGA_MODULE_INFO).expect("embedded naga module info is valid")
+            }
+        },
+    ];
+    // `wgpu::ShaderSource::Naga` needs wgpu's `naga-ir` feature, so is only used when asked for
+    if generator.gen_wgpu && generator.gen_wgpu_naga_ir {
+        items.push(parse_quote! {
+            pub fn shader_source() -> wgpu::ShaderSource<'static> {
+                wgpu::ShaderSource::Naga(::std::borrow::Cow::Owned(naga_module()))
+            }
+        });
+    }
+
+    Ok(items)
+}
@@ -5,130 +5,391 @@ use std::{
 };
 
 use naga_oil::compose::{
-    ComposableModuleDescriptor, Composer, NagaModuleDescriptor, ShaderDefValue, ShaderLanguage,
+    Composer, ComposerError, ComposerErrorInner, ErrSource, NagaModuleDescriptor, ShaderDefValue,
 };
+use regex::Regex;
 
 use crate::{
-    exports::{strip_exports, Export},
+    composer::CachingComposer,
+    error::GenerateError,
+    exports::{parse_exports, Export},
     files::AbsoluteWGSLFilePathBuf,
+    generator::ImportedDefines,
     imports::ImportOrder,
-    module::Module,
+    module::{Module, SearchPaths},
+    preprocess,
 };
 
+lazy_static::lazy_static! {
+    /// Matches the bindings that naga_oil numbers itself, as naga_oil does.
+    static ref AUTO_BINDING_REGEX: Regex = Regex::new(r"@binding\(auto\)").unwrap();
+}
+
+/// The number of times that defines exported by imported files may change which files are imported before giving up.
+const MAX_EXPORTED_DEFINE_PASSES: usize = 16;
+
 /// Shader sourcecode generated from the token stream provided
 pub(crate) struct Sourcecode {
+    name: String,
     exports: HashSet<Export>,
     root_module: Module,
-    project_root: PathBuf,
-    dependents: Vec<AbsoluteWGSLFilePathBuf>,
+    search_paths: SearchPaths,
+    /// Every file imported, directly or indirectly, along with the name given to it within naga_oil.
+    dependents: Vec<(String, AbsoluteWGSLFilePathBuf)>,
+    /// The processed source of the root module, as last given to naga_oil.
+    root_source: String,
+    /// The shader defs last given to naga_oil along with the root module.
+    root_defs: HashMap<String, ShaderDefValue>,
+    /// Files that imports would have resolved to instead, had they existed.
+    missed_imports: Vec<PathBuf>,
 }
 
 impl Sourcecode {
-    pub(crate) fn new(project_root: PathBuf, path: &str) -> Result<Self, String> {
-        let source_path = project_root.join(path);
+    pub(crate) fn new(search_paths: SearchPaths, path: &str) -> Result<Self, GenerateError> {
+        let source_path = search_paths.project_root().join(path);
+        let unresolved = |message: String| GenerateError::ImportResolution {
+            shader: path.to_owned(),
+            message,
+        };
         if !source_path.is_file() {
             if source_path.exists() {
-                return Err(format!(
+                return Err(unresolved(format!(
                     "could not find import `{}`: `{}` exists but is not a file",
                     path,
                     source_path.display()
-                ));
+                )));
             }
-            return Err(format!(
+            return Err(unresolved(format!(
                 "could not find import `{}`: `{}` does not exist",
                 path,
                 source_path.display()
-            ));
+            )));
         }
-        assert!(source_path.is_absolute());
+        // The project root may be given relative to the working directory
+        let source_path = source_path
+            .canonicalize()
+            .map_err(|error| GenerateError::Io {
+                path: source_path.clone(),
+                error,
+            })?;
         if source_path.extension() != Some(OsStr::new("wgsl")) {
-            return Err(format!(
+            return Err(unresolved(format!(
                 "file `{}` does not have the required `.wgsl` extension",
                 path,
-            ));
+            )));
         };
 
         let source_path = AbsoluteWGSLFilePathBuf::new(source_path);
 
         // Calculate top level exports
-        let root_src = std::fs::read_to_string(source_path.as_path()).expect("asserted was file");
-        let (_, exports) = strip_exports(&root_src);
+        let root_src =
+            std::fs::read_to_string(source_path.as_path()).map_err(|error| GenerateError::Io {
+                path: source_path.to_path_buf(),
+                error,
+            })?;
+        let exports = parse_exports(&root_src).map_err(|message| GenerateError::Codegen {
+            shader: path.to_owned(),
+            message,
+        })?;
 
         Ok(Self {
+            name: path.to_owned(),
             root_module: Module::from_path(source_path),
-            project_root,
+            search_paths,
             exports,
             dependents: Vec::new(),
+            root_source: String::new(),
+            root_defs: HashMap::new(),
+            missed_imports: Vec::new(),
         })
     }
 
     /// Uses naga_oil to process includes
     pub(crate) fn compose(
         &mut self,
-        composer: &mut Composer,
+        composer: &mut CachingComposer,
         shader_defs: HashMap<String, ShaderDefValue>,
-    ) -> Result<naga::Module, String> {
+        imported_defines: ImportedDefines,
+    ) -> Result<naga::Module, GenerateError> {
+        // naga_oil applies the top-level file's defines to every import, so they decide which imports are used too
+        let root_defines = self
+            .root_module
+            .defines()
+            .map_err(|error| GenerateError::Io {
+                path: self.root_module.path().to_path_buf(),
+                error,
+            })?;
+        let mut given_defs = shader_defs;
+        given_defs.extend(root_defines.clone());
+
+        // Exported defines can change which files are imported, so are gathered until they stop changing. Imports
+        // that don't resolve may be within blocks that later defines remove, so are only reported once they're known
+        let mut effective_defs = given_defs.clone();
+        if imported_defines == ImportedDefines::Exported {
+            let mut attempts = 0;
+            loop {
+                let import_order = ImportOrder::calculate(
+                    &self.root_module,
+                    &self.search_paths,
+                    &effective_defs,
+                    imported_defines,
+                    true,
+                )
+                .map_err(|err| err.into_generate_error(&self.name))?;
+
+                let mut defs = given_defs.clone();
+                defs.extend(self.exported_defines(&import_order, &root_defines)?);
+                if defs == effective_defs {
+                    break;
+                }
+                attempts += 1;
+                if attempts > MAX_EXPORTED_DEFINE_PASSES {
+                    return Err(self.compose_error(
+                        "defines in imported files keep changing which files are imported"
+                            .to_owned(),
+                    ));
+                }
+                effective_defs = defs;
+            }
+        }
+
         // Traverses the imports in each file, starting with the file given by this object, to give all of the files required
         // and the order in which they need to be processed.
-        let import_order = match ImportOrder::calculate(&self.root_module, &self.project_root) {
-            Ok(import_order) => import_order,
-            Err(err) => {
-                return Err(format!("{}", err));
-            }
-        };
+        let import_order = ImportOrder::calculate(
+            &self.root_module,
+            &self.search_paths,
+            &effective_defs,
+            imported_defines,
+            false,
+        )
+        .map_err(|err| err.into_generate_error(&self.name))?;
 
         // Calculate names of imports
         let reduced_names = import_order.reduced_names();
+        self.missed_imports = import_order.missed().to_vec();
 
-        // Add imports in order to naga-oil
+        // Process imports in order for naga-oil
+        let mut imports = Vec::new();
         for import in import_order.modules() {
             let path = import.path();
-            self.dependents.push(path.clone());
-
-            let source = import.processed_source(&reduced_names, &self.project_root);
-            if source.contains("#define") {
-                return Err(format!(
-                    "imported shader file `{}` contained a `#define` statement \
-                    - only top-level files may contain preprocessor definitions",
-                    path.to_string_lossy().to_string(),
-                ));
-            }
+            self.dependents
+                .push((reduced_names[&import].clone(), path.clone()));
 
-            let desc = ComposableModuleDescriptor {
-                source: &source,
-                file_path: path.to_str().unwrap(),
-                language: ShaderLanguage::Wgsl,
-                as_name: Some(reduced_names[&import].clone()),
-                additional_imports: &[],
-                shader_defs: shader_defs.clone(),
+            let io_error = |error| GenerateError::Io {
+                path: path.to_path_buf(),
+                error,
             };
-            if let Err(e) = composer.add_composable_module(desc) {
-                return Err(crate::error::format_compose_error(e, &composer));
-            }
+            let defines = import.defines().map_err(io_error)?;
+            let module_defs = match imported_defines {
+                ImportedDefines::Scoped => effective_defs
+                    .clone()
+                    .into_iter()
+                    .chain(defines.clone())
+                    .collect(),
+                ImportedDefines::Exported => effective_defs.clone(),
+            };
+            let source = import
+                .processed_source(&reduced_names, &self.search_paths, &module_defs)
+                .map_err(io_error)?;
+            // naga_oil rejects defines in imported files, so they are applied here instead
+            let source = match imported_defines {
+                _ if defines.is_empty() => source,
+                ImportedDefines::Scoped => {
+                    preprocess::apply_scoped_defines(&source, &module_defs, &defines)
+                }
+                ImportedDefines::Exported => preprocess::strip_defines(&source),
+            };
+            imports.push((reduced_names[&import].clone(), path.clone(), source));
         }
 
         // Add main module to link everything
+        let root_source = self
+            .root_module
+            .processed_source(&reduced_names, &self.search_paths, &effective_defs)
+            .map_err(|error| GenerateError::Io {
+                path: self.root_module.path().to_path_buf(),
+                error,
+            })?;
+
+        // Auto bindings are numbered across every module added to a composer, so are only deterministic when the
+        // shader's modules are the only ones it has numbered
+        if std::iter::once(&root_source)
+            .chain(imports.iter().map(|(_, _, source)| source))
+            .any(|source| AUTO_BINDING_REGEX.is_match(source))
+        {
+            composer.reset();
+        }
+
+        // Add imports in order to naga-oil
+        for (name, path, source) in imports {
+            if let Err(e) = composer.add_composable_module(&name, &path.to_string_lossy(), &source)
+            {
+                return Err(self.compose_error(crate::error::format_compose_error(*e, composer)));
+            }
+        }
+
+        self.root_source = root_source;
+        self.root_defs = effective_defs;
+        self.make_naga_module(composer)
+            .map_err(|e| self.compose_error(crate::error::format_compose_error(*e, composer)))
+    }
+
+    /// Links the root module with the modules already added to the composer.
+    fn make_naga_module(
+        &self,
+        composer: &mut Composer,
+    ) -> Result<naga::Module, Box<ComposerError>> {
         composer
             .make_naga_module(NagaModuleDescriptor {
-                source: &self
-                    .root_module
-                    .processed_source(&reduced_names, &self.project_root),
-                file_path: &self.root_module.path().to_string_lossy().to_string(),
+                source: &self.root_source,
+                file_path: &self.root_module.path().to_string_lossy(),
                 additional_imports: &[],
-                shader_defs,
+                shader_defs: self.root_defs.clone(),
                 shader_type: naga_oil::compose::ShaderType::Wgsl,
             })
-            .map_err(|e| crate::error::format_compose_error(e, &composer))
+            .map_err(Box::new)
+    }
+
+    /// Gathers the defines of every file imported, to apply to the whole shader, giving an error if any two files
+    /// define the same name with different values.
+    fn exported_defines(
+        &self,
+        import_order: &ImportOrder,
+        root_defines: &HashMap<String, ShaderDefValue>,
+    ) -> Result<HashMap<String, ShaderDefValue>, GenerateError> {
+        let root_path = self.root_module.path();
+        let mut imports = import_order
+            .reduced_names()
+            .into_keys()
+            .filter(|import| import.path() != root_path)
+            .collect::<Vec<_>>();
+        imports.sort_by_key(|import| import.path().to_path_buf());
+
+        let mut defined_by = root_defines
+            .iter()
+            .map(|(name, value)| (name.clone(), (*value, root_path.to_path_buf())))
+            .collect::<HashMap<_, _>>();
+        for import in imports {
+            let path = import.path().to_path_buf();
+            let defines = import.defines().map_err(|error| GenerateError::Io {
+                path: path.clone(),
+                error,
+            })?;
+            for (name, value) in defines {
+                match defined_by.get(&name) {
+                    Some((other_value, other_path)) if *other_value != value => {
+                        return Err(self.compose_error(format!(
+                            "`{}` and `{}` both define `{}`, with different values",
+                            other_path.display(),
+                            path.display(),
+                            name,
+                        )));
+                    }
+                    Some(_) => {}
+                    None => {
+                        defined_by.insert(name, (value, path.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(defined_by
+            .into_iter()
+            .map(|(name, (value, _))| (name, value))
+            .collect())
+    }
+
+    fn compose_error(&self, message: String) -> GenerateError {
+        GenerateError::Compose {
+            shader: self.name.clone(),
+            message,
+        }
+    }
+
+    /// Describes an error found while validating the module produced by the last call to [`Self::compose`].
+    pub(crate) fn validation_error(
+        &self,
+        e: &naga::WithSpan<naga::valid::ValidationError>,
+        composer: &mut Composer,
+    ) -> GenerateError {
+        let err_source = self.validation_error_source(e, composer);
+        GenerateError::Validation {
+            shader: self.name.clone(),
+            message: crate::error::format_validation_error(e, composer, &err_source),
+        }
+    }
+
+    /// Finds the source that naga_oil parsed the file a validation error originated in from, which differs from the
+    /// file by substituted imports and a header declaring the items imported.
+    ///
+    /// naga_oil only gives this within its own errors, so it is asked to validate the module itself. Its validator
+    /// checks everything, so may find an error in another file first, in which case the file's own source is used.
+    fn validation_error_source(
+        &self,
+        e: &naga::WithSpan<naga::valid::ValidationError>,
+        composer: &mut Composer,
+    ) -> ErrSource {
+        let module_index = crate::error::validation_error_module(e);
+
+        let validate = std::mem::replace(&mut composer.validate, true);
+        let revalidated = self.make_naga_module(composer);
+        composer.validate = validate;
+        if let Err(e) = revalidated {
+            if let ComposerErrorInner::ShaderValidationError(revalidated) = &e.inner {
+                if crate::error::validation_error_module(revalidated) == module_index {
+                    return e.source;
+                }
+            }
+        }
+
+        match composer.module_index.get(&module_index) {
+            Some(name) if module_index != 0 && composer.module_sets.contains_key(name) => {
+                ErrSource::Module {
+                    name: name.clone(),
+                    offset: 0,
+                    defs: self.root_defs.clone(),
+                }
+            }
+            _ => ErrSource::Constructing {
+                path: self.root_module.path().to_string_lossy().into_owned(),
+                source: self.root_source.clone(),
+                offset: 0,
+            },
+        }
+    }
+
+    /// The entrypoint path that this sourcecode was created from, relative to the project root.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The absolute path of the entrypoint file.
+    pub(crate) fn root_path(&self) -> PathBuf {
+        self.root_module.path().to_path_buf()
+    }
+
+    /// The shader defs that the last call to [`Self::compose`] built the entrypoint and its imports with.
+    pub(crate) fn defs(&self) -> &HashMap<String, ShaderDefValue> {
+        &self.root_defs
     }
 
     pub(crate) fn exports(&self) -> &HashSet<Export> {
         &self.exports
     }
 
-    pub(crate) fn relative_dependents(&self) -> Vec<PathBuf> {
-        self.dependents
-            .iter()
-            .map(|f| f.strip_prefix(&self.project_root).unwrap().to_path_buf())
-            .collect()
+    /// Gives every file imported by the last call to [`Self::compose`], along with the module name given to it.
+    pub(crate) fn imported_modules(&self) -> &[(String, AbsoluteWGSLFilePathBuf)] {
+        &self.dependents
+    }
+
+    /// Gives every file that an import would have resolved to instead, had it existed, as of the last call to
+    /// [`Self::compose`].
+    pub(crate) fn missed_imports(&self) -> &[PathBuf] {
+        &self.missed_imports
+    }
+
+    /// The absolute paths of the files imported as of the last call to [`Self::compose`].
+    pub(crate) fn dependents(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.dependents.iter().map(|(_, f)| f.to_path_buf())
     }
 }
@@ -0,0 +1,483 @@
+use std::{borrow::Cow, collections::HashSet};
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse_quote;
+
+use crate::{
+    exports::{Export, TYPES_MODULE_NAME},
+    layout::sanitize_ident,
+};
+
+/// Gives the name of the struct generated to hold the `@location` arguments of a vertex entry point, if it takes
+/// any, e.g. `VsMainInput` for `vs_main`.
+pub(crate) fn location_inputs_struct_name(entry_point: &naga::EntryPoint) -> Option<String> {
+    let takes_locations = entry_point
+        .function
+        .arguments
+        .iter()
+        .any(|argument| matches!(argument.binding, Some(naga::Binding::Location { .. })));
+    if entry_point.stage != naga::ShaderStage::Vertex || !takes_locations {
+        return None;
+    }
+
+    let mut name = entry_point
+        .name
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().expect("parts are not empty");
+            first.to_uppercase().chain(chars).collect::<String>()
+        })
+        .collect::<String>();
+    name.push_str("Input");
+    Some(name)
+}
+
+/// Adds a struct to the module for each vertex entry point that takes individual `@location` arguments, with a
+/// member for each of those arguments, so that they can be generated in the same way as struct vertex inputs.
+///
+/// The module is only cloned if any structs are added.
+pub(crate) fn add_location_inputs_structs(
+    module: &naga::Module,
+) -> Result<Cow<'_, naga::Module>, String> {
+    let mut module = Cow::Borrowed(module);
+    for i in 0..module.entry_points.len() {
+        let entry_point = &module.entry_points[i];
+        let Some(name) = location_inputs_struct_name(entry_point) else {
+            continue;
+        };
+        if module
+            .types
+            .iter()
+            .any(|(_, ty)| ty.name.as_deref() == Some(name.as_str()))
+        {
+            return Err(format!(
+                "vertex entry point `{}` takes `@location` arguments, but the struct `{}` that would be \
+                generated for them already exists",
+                entry_point.name, name
+            ));
+        }
+
+        let mut layouter = naga::proc::Layouter::default();
+        layouter
+            .update(module.to_ctx())
+            .map_err(|e| format!("failed to compute type layouts: {}", e))?;
+
+        // Lay the members out in the same way as a WGSL struct
+        let mut offset = 0;
+        let mut alignment = naga::proc::Alignment::ONE;
+        let members = entry_point
+            .function
+            .arguments
+            .iter()
+            .filter(|argument| matches!(argument.binding, Some(naga::Binding::Location { .. })))
+            .map(|argument| {
+                let layout = layouter[argument.ty];
+                alignment = alignment.max(layout.alignment);
+                let member_offset = layout.alignment.round_up(offset);
+                offset = member_offset + layout.size;
+                naga::StructMember {
+                    name: argument.name.clone(),
+                    ty: argument.ty,
+                    binding: argument.binding.clone(),
+                    offset: member_offset,
+                }
+            })
+            .collect();
+        let span = alignment.round_up(offset);
+
+        module.to_mut().types.insert(
+            naga::Type {
+                name: Some(name),
+                inner: naga::TypeInner::Struct { members, span },
+            },
+            naga::Span::UNDEFINED,
+        );
+    }
+
+    Ok(module)
+}
+
+/// Adds a `wgpu::VertexBufferLayout` constant to each of the generated structs that are used as vertex inputs,
+/// with attributes taken from the `@location`s of their members.
+///
+/// The constants are implemented within the `types` module, so that they are shared along with the types.
+pub(crate) fn add_vertex_buffer_layouts(
+    items: &mut [syn::Item],
+    module: &naga::Module,
+    vertex_inputs: &HashSet<String>,
+    exports: &HashSet<Export>,
+) -> Result<(), String> {
+    let Some(types) = items.iter_mut().find_map(|item| match item {
+        syn::Item::Mod(syn::ItemMod {
+            ident,
+            content: Some((_, content)),
+            ..
+        }) if ident == TYPES_MODULE_NAME => Some(content),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+
+    // Iterate over the module's types rather than the set, so that output is deterministic
+    for ty in module.types.iter().map(|(_, ty)| ty) {
+        let (Some(name), naga::TypeInner::Struct { members, .. }) = (&ty.name, &ty.inner) else {
+            continue;
+        };
+        if !vertex_inputs.contains(name) {
+            continue;
+        }
+        let generated = types
+            .iter()
+            .any(|item| matches!(item, syn::Item::Struct(item) if item.ident == name));
+        if !generated {
+            continue;
+        }
+
+        let step_mode = step_mode(name, exports)?;
+        let attributes = members
+            .iter()
+            .filter_map(|member| match member.binding {
+                Some(naga::Binding::Location { location, .. }) => Some((location, member)),
+                _ => None,
+            })
+            .map(|(location, member)| {
+                let member_name = member.name.as_deref().ok_or_else(|| {
+                    format!("vertex input `{}` has an unnamed member", name)
+                })?;
+                let format = vertex_format(&module.types[member.ty].inner).ok_or_else(|| {
+                    format!(
+                        "member `{}` of vertex input `{}` does not have a type usable as a vertex attribute",
+                        member_name, name
+                    )
+                })?;
+                // Members named after Rust keywords are generated as raw identifiers
+                let field = syn::parse_str::<syn::Ident>(&sanitize_ident(member_name))
+                    .expect("sanitized names are identifiers");
+                Ok(quote! {
+                    wgpu::VertexAttribute {
+                        format: wgpu::VertexFormat::#format,
+                        offset: ::core::mem::offset_of!(Self, #field) as wgpu::BufferAddress,
+                        shader_location: #location,
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let attribute_count = attributes.len();
+
+        let struct_name = format_ident!("{}", name);
+        types.push(parse_quote! {
+            impl #struct_name {
+                pub const VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; #attribute_count] = [
+                    #(#attributes),*
+                ];
+
+                pub const VERTEX_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+                    array_stride: ::core::mem::size_of::<Self>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::#step_mode,
+                    attributes: &Self::VERTEX_ATTRIBUTES,
+                };
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Gives the step mode of a vertex input, from the `step_mode` option given when exporting it.
+fn step_mode(struct_name: &str, exports: &HashSet<Export>) -> Result<TokenStream, String> {
+    let step_mode = exports.iter().find_map(|export| match export {
+        Export::Struct {
+            struct_name: name,
+            options,
+        } if name == struct_name => options.step_mode.as_deref(),
+        _ => None,
+    });
+
+    match step_mode {
+        None | Some("vertex") => Ok(quote! { Vertex }),
+        Some("instance") => Ok(quote! { Instance }),
+        Some(other) => Err(format!(
+            "invalid `step_mode` for struct `{}`: expected `vertex` or `instance`, found `{}`",
+            struct_name, other
+        )),
+    }
+}
+
+/// Gives the name of the `wgpu::VertexFormat` variant for a vertex attribute type, if it has one.
+fn vertex_format(inner: &naga::TypeInner) -> Option<syn::Ident> {
+    let (scalar, components) = match inner {
+        naga::TypeInner::Scalar(scalar) => (scalar, 1),
+        naga::TypeInner::Vector { size, scalar } => (scalar, *size as u8),
+        _ => return None,
+    };
+    let kind = match (scalar.kind, scalar.width) {
+        (naga::ScalarKind::Float, 4) => "Float32",
+        (naga::ScalarKind::Float, 2) if components % 2 == 0 => "Float16",
+        (naga::ScalarKind::Sint, 4) => "Sint32",
+        (naga::ScalarKind::Uint, 4) => "Uint32",
+        _ => return None,
+    };
+
+    Some(match components {
+        1 => format_ident!("{}", kind),
+        n => format_ident!("{}x{}", kind, n),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gives the items added to the `types` module of a shader for its vertex inputs, given the structs generated
+    /// for them.
+    fn vertex_layouts(source: &str, vertex_inputs: &[&str]) -> Result<String, String> {
+        let exports = crate::exports::parse_exports(source).unwrap();
+        let module = naga::front::wgsl::parse_str(&crate::exports::strip_exports(source)).unwrap();
+        let structs = vertex_inputs.iter().map(|name| format_ident!("{}", name));
+        let mut items = vec![parse_quote! {
+            pub mod types {
+                #(pub struct #structs {})*
+            }
+        }];
+        let vertex_inputs = vertex_inputs.iter().map(|name| name.to_string()).collect();
+        add_vertex_buffer_layouts(&mut items, &module, &vertex_inputs, &exports)?;
+
+        let [syn::Item::Mod(syn::ItemMod {
+            content: Some((_, content)),
+            ..
+        })] = items.as_slice()
+        else {
+            panic!("expected the types module");
+        };
+        let added = content
+            .iter()
+            .filter(|item| matches!(item, syn::Item::Impl(_)));
+        Ok(quote! { #(#added)* }.to_string())
+    }
+
+    #[test]
+    fn attributes_follow_locations_and_formats() {
+        let source = "
+            struct Vertex {
+                @location(0) position: vec3<f32>,
+                @location(2) index: u32,
+                @location(1) offset: vec2<i32>,
+                @builtin(vertex_index) vertex_index: u32,
+            }
+
+            @vertex
+            fn vs_main(vertex: Vertex) -> @builtin(position) vec4<f32> {
+                return vec4(vertex.position, f32(vertex.index + vertex.vertex_index + u32(vertex.offset.x)));
+            }
+        ";
+
+        assert_eq!(
+            vertex_layouts(source, &["Vertex"]).unwrap(),
+            quote! {
+                impl Vertex {
+                    pub const VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 3usize] = [
+                        wgpu::VertexAttribute {
+                            format: wgpu::VertexFormat::Float32x3,
+                            offset: ::core::mem::offset_of!(Self, position) as wgpu::BufferAddress,
+                            shader_location: 0u32,
+                        },
+                        wgpu::VertexAttribute {
+                            format: wgpu::VertexFormat::Uint32,
+                            offset: ::core::mem::offset_of!(Self, index) as wgpu::BufferAddress,
+                            shader_location: 2u32,
+                        },
+                        wgpu::VertexAttribute {
+                            format: wgpu::VertexFormat::Sint32x2,
+                            offset: ::core::mem::offset_of!(Self, offset) as wgpu::BufferAddress,
+                            shader_location: 1u32,
+                        }
+                    ];
+
+                    pub const VERTEX_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+                        array_stride: ::core::mem::size_of::<Self>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &Self::VERTEX_ATTRIBUTES,
+                    };
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn step_mode_is_taken_from_export_options() {
+        let source = "
+            @export(step_mode = \"instance\")
+            struct Instance { @location(0) scale: f32 }
+
+            @vertex
+            fn vs_main(instance: Instance) -> @builtin(position) vec4<f32> {
+                return vec4(instance.scale);
+            }
+        ";
+
+        let layouts = vertex_layouts(source, &["Instance"]).unwrap();
+        assert!(
+            layouts.contains(&quote! { step_mode: wgpu::VertexStepMode::Instance }.to_string()),
+            "{}",
+            layouts
+        );
+
+        let invalid = source.replace("\"instance\"", "\"index\"");
+        assert!(vertex_layouts(&invalid, &["Instance"]).is_err());
+    }
+
+    #[test]
+    fn keyword_members_are_raw_identifiers() {
+        let source = "
+            struct Vertex { @location(0) in: f32 }
+
+            @vertex
+            fn vs_main(vertex: Vertex) -> @builtin(position) vec4<f32> {
+                return vec4(vertex.in);
+            }
+        ";
+
+        let layouts = vertex_layouts(source, &["Vertex"]).unwrap();
+        assert!(
+            layouts.contains(&quote! { ::core::mem::offset_of!(Self, r#in) }.to_string()),
+            "{}",
+            layouts
+        );
+    }
+
+    #[test]
+    fn only_vertex_inputs_get_layouts() {
+        let source = "
+            struct Vertex { @location(0) position: vec4<f32> }
+
+            @vertex
+            fn vs_main(vertex: Vertex) -> @builtin(position) vec4<f32> {
+                return vertex.position;
+            }
+        ";
+
+        assert_eq!(vertex_layouts(source, &[]).unwrap(), "");
+    }
+
+    /// The name of a location inputs struct, with the name and offset of each member.
+    type LocationInputs = (String, Vec<(String, u32)>);
+
+    /// Gives the location inputs struct added for the first vertex entry point of a shader that needs one.
+    fn location_inputs_struct(source: &str) -> Result<Option<LocationInputs>, String> {
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let name = module
+            .entry_points
+            .iter()
+            .find_map(location_inputs_struct_name);
+        let module = add_location_inputs_structs(&module)?;
+        Ok(name.map(|name| {
+            let members = module
+                .types
+                .iter()
+                .find_map(|(_, ty)| match &ty.inner {
+                    naga::TypeInner::Struct { members, .. }
+                        if ty.name.as_deref() == Some(&name) =>
+                    {
+                        Some(members)
+                    }
+                    _ => None,
+                })
+                .expect("the struct was added")
+                .iter()
+                .map(|member| (member.name.clone().unwrap(), member.offset))
+                .collect();
+            (name, members)
+        }))
+    }
+
+    #[test]
+    fn location_inputs_are_named_after_entry_points() {
+        let source = |name: &str| {
+            format!(
+                "@vertex fn {}(@location(0) position: vec4<f32>) -> @builtin(position) vec4<f32> {{ return position; }}",
+                name
+            )
+        };
+
+        for (entry_point, name) in [
+            ("vs_main", "VsMainInput"),
+            ("main", "MainInput"),
+            ("vs__shadow_", "VsShadowInput"),
+            ("vsMain", "VsMainInput"),
+        ] {
+            let (struct_name, _) = location_inputs_struct(&source(entry_point))
+                .unwrap()
+                .unwrap();
+            assert_eq!(struct_name, name);
+        }
+    }
+
+    #[test]
+    fn location_inputs_are_only_made_for_vertex_locations() {
+        // Builtins only
+        let source = "
+            @vertex
+            fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+                return vec4(f32(index));
+            }
+        ";
+        assert_eq!(location_inputs_struct(source), Ok(None));
+
+        // Fragment inputs
+        let source = "
+            @fragment
+            fn fs_main(@location(0) color: vec4<f32>) -> @location(0) vec4<f32> {
+                return color;
+            }
+        ";
+        assert_eq!(location_inputs_struct(source), Ok(None));
+    }
+
+    #[test]
+    fn location_inputs_keep_argument_order() {
+        let source = "
+            @vertex
+            fn vs_main(
+                @location(2) weight: f32,
+                @builtin(vertex_index) index: u32,
+                @location(0) position: vec3<f32>,
+                @location(1) uv: vec2<f32>,
+            ) -> @builtin(position) vec4<f32> {
+                return vec4(position, weight + uv.x + f32(index));
+            }
+        ";
+
+        assert_eq!(
+            location_inputs_struct(source),
+            Ok(Some((
+                "VsMainInput".to_owned(),
+                vec![
+                    ("weight".to_owned(), 0),
+                    ("position".to_owned(), 16),
+                    ("uv".to_owned(), 32),
+                ]
+            )))
+        );
+    }
+
+    #[test]
+    fn location_inputs_colliding_with_a_struct_are_an_error() {
+        let source = "
+            struct VsMainInput { value: f32 }
+
+            @vertex
+            fn vs_main(@location(0) position: vec4<f32>) -> @builtin(position) vec4<f32> {
+                let input = VsMainInput(1.0);
+                return position * input.value;
+            }
+        ";
+
+        let error = location_inputs_struct(source).unwrap_err();
+        assert!(error.contains("`VsMainInput`"), "{}", error);
+        assert!(error.contains("`vs_main`"), "{}", error);
+    }
+}
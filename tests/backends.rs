@@ -0,0 +1,94 @@
+#![cfg(feature = "glsl")]
+
+use common::project;
+use generate_wgsl_oil::{glsl, GenerateError, GenerateErrors, Generator};
+use tempfile::TempDir;
+
+mod common;
+
+/// A shader with a compute and a fragment entry point.
+fn shader() -> TempDir {
+    project(&[(
+        "main.wgsl",
+        "\
+@compute @workgroup_size(1)
+fn main_cs() {}
+
+@fragment
+fn main_fs() -> @location(0) vec4<f32> { return vec4(1.0); }
+",
+    )])
+}
+
+fn generate(project: &TempDir, version: glsl::Version) -> Result<String, GenerateErrors> {
+    Generator::new()
+        .project_root(project.path())
+        .entrypoint("main.wgsl")
+        .glsl_options(glsl::Options {
+            version,
+            ..Default::default()
+        })
+        .cache(false)
+        .generate()
+}
+
+#[test]
+fn glsl_options_are_used() {
+    let project = shader();
+
+    let generated = generate(&project, glsl::Version::Desktop(450)).unwrap();
+    assert!(generated.contains("MAIN_CS"), "{}", generated);
+    assert!(generated.contains("MAIN_FS"), "{}", generated);
+    assert!(generated.contains("#version 450 core"), "{}", generated);
+}
+
+#[test]
+fn every_failing_entry_point_is_reported() {
+    let project = shader();
+
+    // Compute shaders need OpenGL ES 3.1, so only the compute entry point fails
+    let errors = generate(&project, glsl::Version::new_gles(300)).unwrap_err();
+    let [GenerateError::Codegen { message, .. }] = errors.errors() else {
+        panic!("expected a single error, found {:?}", errors.errors());
+    };
+    assert!(message.contains("`main_cs`"), "{}", message);
+    assert!(!message.contains("`main_fs`"), "{}", message);
+}
+
+#[test]
+fn shaders_with_overrides_without_defaults_are_not_translated() {
+    let project = project(&[
+        (
+            "required.wgsl",
+            "\
+override scale: f32;
+
+@fragment
+fn main() -> @location(0) vec4<f32> { return vec4(scale); }
+",
+        ),
+        (
+            "defaulted.wgsl",
+            "\
+override scale: f32 = 2.0;
+
+@fragment
+fn main() -> @location(0) vec4<f32> { return vec4(scale); }
+",
+        ),
+    ]);
+
+    let generate = |entrypoint: &str| {
+        Generator::new()
+            .project_root(project.path())
+            .entrypoint(entrypoint)
+            .cache(false)
+            .generate()
+            .unwrap()
+    };
+
+    let generated = generate("required.wgsl");
+    assert!(!generated.contains("pub mod glsl"), "{}", generated);
+    let generated = generate("defaulted.wgsl");
+    assert!(generated.contains("pub mod glsl"), "{}", generated);
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "wgpu")]
+
+use common::project;
+use generate_wgsl_oil::Generator;
+
+mod common;
+
+fn shader(name: &str) -> (String, String) {
+    let source = "\
+#import common.wgsl
+
+@group(1) @binding(auto) var<uniform> first: f32;
+@group(1) @binding(auto) var<uniform> second: f32;
+
+@compute @workgroup_size(1)
+fn main() { let value = first + second + common::scale; }
+";
+    (format!("{}.wgsl", name), source.to_owned())
+}
+
+#[test]
+fn auto_bindings_do_not_depend_on_thread_count() {
+    let shaders = ["a", "b", "c", "d"].map(shader);
+    let mut files = vec![(
+        "common.wgsl",
+        "@group(0) @binding(auto) var<uniform> scale: f32;\n",
+    )];
+    files.extend(
+        shaders
+            .iter()
+            .map(|(path, source)| (path.as_str(), source.as_str())),
+    );
+    let project = project(&files);
+
+    let generate = |threads| {
+        Generator::new()
+            .project_root(project.path())
+            .entrypoints(shaders.iter().map(|(path, _)| path.clone()))
+            .wgpu(true)
+            .cache(false)
+            .threads(threads)
+            .generate()
+            .unwrap()
+    };
+    let single_threaded = generate(1);
+
+    assert_eq!(single_threaded, generate(4));
+    // Every shader numbers its bindings as if it were the only one composed
+    assert!(!single_threaded.contains("binding: 3u32"), "{}", single_threaded);
+}
@@ -0,0 +1,43 @@
+use common::project;
+use generate_wgsl_oil::Generator;
+
+mod common;
+
+// The cache is kept in `OUT_DIR`, so everything using it is within one test
+#[test]
+fn cached_shaders_are_composed_again_when_their_files_change() {
+    let project = project(&[
+        ("lib.wgsl", "fn one() -> f32 { return 1.0; }\n"),
+        (
+            "shaders/main.wgsl",
+            "\
+#import lib.wgsl
+
+@compute @workgroup_size(1)
+fn main() { let value = lib::one(); }
+",
+        ),
+    ]);
+    std::env::set_var("OUT_DIR", project.path().join("out"));
+    let generate = || {
+        Generator::new()
+            .project_root(project.path())
+            .entrypoint("shaders/main.wgsl")
+            .threads(1)
+            .generate()
+    };
+    let generated = generate().unwrap();
+    assert_eq!(generate().unwrap(), generated);
+
+    // An import of the same name next to the shader takes precedence over the one in the project root
+    let shadowing = project.path().join("shaders/lib.wgsl");
+    std::fs::write(&shadowing, "fn one() -> f32 { return true; }\n").unwrap();
+    assert!(generate().is_err());
+    std::fs::remove_file(&shadowing).unwrap();
+    assert_eq!(generate().unwrap(), generated);
+
+    // Changes to imports are picked up
+    let lib = project.path().join("lib.wgsl");
+    std::fs::write(&lib, "fn one() -> f32 { return false; }\n").unwrap();
+    assert!(generate().is_err());
+}
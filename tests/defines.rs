@@ -0,0 +1,120 @@
+use common::project;
+use generate_wgsl_oil::{GenerateError, GenerateErrors, Generator, ImportedDefines};
+use tempfile::TempDir;
+
+mod common;
+
+fn generate(project: &TempDir, imported_defines: ImportedDefines) -> Result<String, GenerateErrors> {
+    Generator::new()
+        .project_root(project.path())
+        .entrypoint("main.wgsl")
+        .imported_defines(imported_defines)
+        .cache(false)
+        .generate()
+}
+
+/// Gives the message of the single error from generating a shader.
+fn error_message(errors: GenerateErrors) -> String {
+    match errors.errors() {
+        [GenerateError::ImportResolution { message, .. } | GenerateError::Compose { message, .. }] => {
+            message.clone()
+        }
+        errors => panic!("expected a single error, found {:?}", errors),
+    }
+}
+
+/// `lib.wgsl` defines `FAST`, which chooses its own import, while `main.wgsl` only imports `missing.wgsl` if it sees
+/// the define too.
+fn define_switching_imports() -> TempDir {
+    project(&[
+        ("fast.wgsl", "fn value() -> f32 { return 1.0; }\n"),
+        (
+            "lib.wgsl",
+            "\
+#define FAST
+
+#ifdef FAST
+#import fast.wgsl
+#else
+#import slow.wgsl
+#endif
+
+fn value() -> f32 {
+#ifdef FAST
+    return fast::value();
+#else
+    return slow::value();
+#endif
+}
+",
+        ),
+        (
+            "main.wgsl",
+            "\
+#import lib.wgsl
+#ifdef FAST
+#import missing.wgsl
+#endif
+
+@compute @workgroup_size(1)
+fn main() { let value = lib::value(); }
+",
+        ),
+    ])
+}
+
+#[test]
+fn scoped_define_switches_import_of_declaring_file() {
+    let project = define_switching_imports();
+
+    generate(&project, ImportedDefines::Scoped).unwrap();
+}
+
+#[test]
+fn exported_define_switches_imports_of_importing_file() {
+    let project = define_switching_imports();
+
+    let message = error_message(generate(&project, ImportedDefines::Exported).unwrap_err());
+    assert!(message.contains("missing.wgsl"), "{}", message);
+}
+
+/// `a.wgsl` and `b.wgsl` both define `COUNT`, with different values.
+fn conflicting_defines() -> TempDir {
+    project(&[
+        (
+            "a.wgsl",
+            "#define COUNT 1\n\nfn count() -> u32 { return #{COUNT}u; }\n",
+        ),
+        (
+            "b.wgsl",
+            "#define COUNT 2\n\nfn count() -> u32 { return #{COUNT}u; }\n",
+        ),
+        (
+            "main.wgsl",
+            "\
+#import a.wgsl
+#import b.wgsl
+
+@compute @workgroup_size(1)
+fn main() { let count = a::count() + b::count(); }
+",
+        ),
+    ])
+}
+
+#[test]
+fn scoped_defines_may_differ_between_files() {
+    let project = conflicting_defines();
+
+    generate(&project, ImportedDefines::Scoped).unwrap();
+}
+
+#[test]
+fn exported_defines_must_agree_between_files() {
+    let project = conflicting_defines();
+
+    let message = error_message(generate(&project, ImportedDefines::Exported).unwrap_err());
+    assert!(message.contains("`COUNT`"), "{}", message);
+    assert!(message.contains("a.wgsl"), "{}", message);
+    assert!(message.contains("b.wgsl"), "{}", message);
+}
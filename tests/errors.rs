@@ -0,0 +1,153 @@
+use common::project;
+use generate_wgsl_oil::{GenerateError, Generator};
+use tempfile::TempDir;
+
+mod common;
+
+/// Generates code for an entrypoint that is expected to fail validation, giving the error message.
+fn validation_error(project: &TempDir, entrypoint: &str) -> String {
+    let errors = Generator::new()
+        .project_root(project.path())
+        .entrypoint(entrypoint)
+        .cache(false)
+        .generate()
+        .expect_err("the shader is invalid");
+    match errors.errors() {
+        [GenerateError::Validation { message, .. }] => message.clone(),
+        errors => panic!("expected a single validation error, found {:?}", errors),
+    }
+}
+
+const UTIL: &str = "fn one() -> f32 { return 1.0; }\n";
+
+const LIB: &str = "\
+#import util.wgsl
+
+fn helper() -> f32 { return util::one(); }
+
+var<uniform> lib_flag: bool;
+
+fn uses_flag() -> bool { return lib_flag; }
+";
+
+#[test]
+fn validation_error_in_shader_with_imports_reports_line() {
+    let project = project(&[
+        ("util.wgsl", UTIL),
+        ("lib.wgsl", LIB),
+        (
+            "main.wgsl",
+            "\
+#import lib.wgsl
+
+var<uniform> flag: bool;
+
+@fragment
+fn main() -> @location(0) vec4<f32> {
+    if flag { return vec4<f32>(0.0); }
+    return vec4<f32>(lib::helper());
+}
+",
+        ),
+    ]);
+
+    let message = validation_error(&project, "main.wgsl");
+    assert!(message.contains("main.wgsl:3:1"), "{}", message);
+    assert!(message.contains("var<uniform> flag: bool;"), "{}", message);
+}
+
+#[test]
+fn validation_error_in_imported_file_reports_line() {
+    let project = project(&[
+        ("util.wgsl", UTIL),
+        ("lib.wgsl", LIB),
+        (
+            "main.wgsl",
+            "\
+#import lib.wgsl
+
+@fragment
+fn main() -> @location(0) vec4<f32> {
+    if lib::uses_flag() { return vec4<f32>(0.0); }
+    return vec4<f32>(lib::helper());
+}
+",
+        ),
+    ]);
+
+    let message = validation_error(&project, "main.wgsl");
+    assert!(message.contains("lib.wgsl:5:1"), "{}", message);
+    assert!(message.contains("var<uniform> lib_flag: bool;"), "{}", message);
+}
+
+#[test]
+fn missing_out_dir_is_reported() {
+    // Only cargo sets `OUT_DIR`, for build scripts
+    std::env::remove_var("OUT_DIR");
+    let project = project(&[("main.wgsl", UTIL)]);
+
+    let errors = Generator::new()
+        .project_root(project.path())
+        .entrypoint("main.wgsl")
+        .cache(false)
+        .write_to_out_dir()
+        .unwrap_err();
+    match errors.errors() {
+        [GenerateError::Env { variable, .. }] => assert_eq!(variable, "OUT_DIR"),
+        errors => panic!("expected a single error, found {:?}", errors),
+    }
+}
+
+// Import paths always end in `.wgsl`, but may be links to files that don't
+#[cfg(unix)]
+#[test]
+fn imports_resolving_to_files_that_are_not_wgsl_are_reported() {
+    let project = project(&[
+        ("data.txt", "fn one() -> f32 { return 1.0; }\n"),
+        (
+            "main.wgsl",
+            "\
+#import data.wgsl
+
+@compute @workgroup_size(1)
+fn main() {}
+",
+        ),
+    ]);
+    std::os::unix::fs::symlink(
+        project.path().join("data.txt"),
+        project.path().join("data.wgsl"),
+    )
+    .unwrap();
+
+    let errors = Generator::new()
+        .project_root(project.path())
+        .entrypoint("main.wgsl")
+        .cache(false)
+        .generate()
+        .unwrap_err();
+    match errors.errors() {
+        [GenerateError::ImportResolution { message, .. }] => {
+            assert!(message.contains("`data.wgsl`"), "{}", message);
+            assert!(message.contains("on line 1"), "{}", message);
+            assert!(message.contains("`.wgsl` extension"), "{}", message);
+        }
+        errors => panic!("expected a single error, found {:?}", errors),
+    }
+}
+
+#[test]
+fn entrypoints_that_do_not_name_files_are_reported() {
+    let project = project(&[]);
+
+    let errors = Generator::new()
+        .project_root(project.path())
+        .entrypoint("..")
+        .cache(false)
+        .generate()
+        .unwrap_err();
+    match errors.errors() {
+        [GenerateError::ImportResolution { shader, .. }] => assert_eq!(shader, ".."),
+        errors => panic!("expected a single error, found {:?}", errors),
+    }
+}
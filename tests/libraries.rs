@@ -0,0 +1,118 @@
+use common::project;
+use generate_wgsl_oil::{GenerateError, Generator, ShaderDefValue};
+use tempfile::TempDir;
+
+mod common;
+
+fn generator(project: &TempDir) -> Generator {
+    Generator::new()
+        .project_root(project.path())
+        .cache(false)
+        .threads(1)
+}
+
+const UTILS: &str = "@export struct Data { value: f32 }\n";
+
+const MAIN: &str = "\
+#import utils.wgsl
+
+@compute @workgroup_size(1)
+fn main() { let data = utils::Data(1.0); }
+";
+
+#[test]
+fn libraries_with_the_same_file_name_get_their_own_modules() {
+    let project = project(&[
+        ("a/utils.wgsl", UTILS),
+        ("a/main.wgsl", MAIN),
+        ("b/utils.wgsl", UTILS),
+        ("b/main.wgsl", MAIN),
+    ]);
+    let generated = generator(&project)
+        .entrypoint("a/main.wgsl")
+        .entrypoint("b/main.wgsl")
+        .generate()
+        .unwrap();
+
+    assert!(generated.contains("pub mod utils_a "), "{}", generated);
+    assert!(generated.contains("pub mod utils_b "), "{}", generated);
+}
+
+#[test]
+fn library_named_like_an_entrypoint_gets_its_own_module() {
+    let project = project(&[
+        ("utils.wgsl", "@compute @workgroup_size(1)\nfn main() {}\n"),
+        ("lib/utils.wgsl", UTILS),
+        (
+            "main.wgsl",
+            "\
+#import lib/utils.wgsl
+
+@compute @workgroup_size(1)
+fn main() { let data = utils::Data(1.0); }
+",
+        ),
+    ]);
+    let generated = generator(&project)
+        .entrypoint("utils.wgsl")
+        .entrypoint("main.wgsl")
+        .generate()
+        .unwrap();
+
+    assert!(generated.contains("pub mod utils_lib "), "{}", generated);
+}
+
+#[test]
+fn library_is_composed_with_its_importers_defs() {
+    let project = project(&[
+        (
+            "utils.wgsl",
+            "\
+#ifdef SCALE
+const SCALE: f32 = 2.0;
+#endif
+
+@export struct Data { value: f32 }
+
+fn scaled(data: Data) -> f32 { return data.value * SCALE; }
+",
+        ),
+        ("main.wgsl", MAIN),
+    ]);
+    let generated = generator(&project)
+        .entrypoint_with_defs("main.wgsl", [("SCALE", ShaderDefValue::Bool(true))])
+        .generate()
+        .unwrap();
+
+    assert!(generated.contains("pub mod utils "), "{}", generated);
+}
+
+#[test]
+fn struct_not_exported_by_its_library_is_unresolved() {
+    let project = project(&[
+        (
+            "utils.wgsl",
+            "@export struct Data { value: f32 }\nstruct Hidden { value: f32 }\n",
+        ),
+        (
+            "main.wgsl",
+            "\
+#import utils.wgsl
+
+@export struct Light { hidden: utils::Hidden }
+
+@compute @workgroup_size(1)
+fn main() { let light = Light(utils::Hidden(1.0)); }
+",
+        ),
+    ]);
+    let errors = generator(&project)
+        .entrypoint("main.wgsl")
+        .generate()
+        .unwrap_err();
+
+    let [GenerateError::Codegen { message, .. }] = errors.errors() else {
+        panic!("expected a single error, found {:?}", errors.errors());
+    };
+    assert!(message.contains("`Hidden`"), "{}", message);
+}